@@ -1,4 +1,16 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/lightning.proto")?;
+    tonic_build::configure().compile(
+        &[
+            "proto/lightning.proto",
+            "proto/router.proto",
+            "proto/walletkit.proto",
+            "proto/signer.proto",
+        ],
+        &["proto"],
+    )?;
+    tonic_build::configure().compile(
+        &["proto/greenlight.proto", "proto/scheduler.proto"],
+        &["proto"],
+    )?;
     Ok(())
 }
@@ -0,0 +1,120 @@
+use crate::config::{self, Config};
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(name = "sphinx-swarm", about = "Run a swarm of sphinx nodes in docker")]
+pub struct Cli {
+    /// Project name, used for vol/{project} and container labels
+    #[arg(long, global = true, value_parser = parse_project)]
+    pub project: Option<String>,
+    /// Override the network from the config file
+    #[arg(long, global = true, value_parser = ["regtest", "bitcoin"])]
+    pub network: Option<String>,
+    /// Path to the swarm config file [default: vol/{project}/config.json]
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+    /// Port for the web UI and API
+    #[arg(long, global = true, env = "ROCKET_PORT", default_value_t = 8000)]
+    pub port: u16,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the swarm described by the config file
+    #[command(alias = "stack")]
+    Up,
+    /// Remove the containers of a project
+    Down {
+        /// Only list the containers that would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show the containers of a project
+    Status,
+    /// Print the logs of a node
+    Logs {
+        /// Node name, e.g. lnd1
+        node: String,
+        /// Keep streaming new log lines
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Run a command inside a node's container
+    Exec {
+        /// Node name, e.g. lnd1
+        node: String,
+        /// Command and its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        cmd: Vec<String>,
+    },
+    /// Rotate the secret of a node and recreate what depends on it
+    ///
+    /// bitcoind gets a new rpc password, a proxy a new admin token,
+    /// and lnd a new wallet password
+    Rotate {
+        /// Node name, e.g. bitcoind
+        node: String,
+    },
+    /// Run the CLN demo
+    Demo,
+    /// Start a single bitcoind
+    Test,
+}
+
+// the project ends up in paths, container names and docker labels,
+// so it follows docker's container name rules: [a-zA-Z0-9][a-zA-Z0-9_.-]*
+fn parse_project(s: &str) -> Result<String, String> {
+    let mut chars = s.chars();
+    let valid = match chars.next() {
+        Some(c) => {
+            c.is_ascii_alphanumeric()
+                && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        }
+        None => false,
+    };
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err("must match [a-zA-Z0-9][a-zA-Z0-9_.-]*".to_string())
+    }
+}
+
+impl Cli {
+    pub fn project(&self) -> String {
+        let default = match self.command {
+            Command::Demo => "demo",
+            Command::Test => "test",
+            _ => "stack",
+        };
+        self.project.clone().unwrap_or(default.to_string())
+    }
+    pub fn load_config(&self) -> Result<Config> {
+        let project = self.project();
+        let mut conf = match &self.config {
+            Some(path) => config::read_config(path)?,
+            None => config::load_config(&project)?,
+        };
+        if let Some(network) = &self.network {
+            conf.network = network.clone();
+        }
+        Ok(conf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_project() {
+        assert!(parse_project("stack").is_ok());
+        assert!(parse_project("my-swarm_1.0").is_ok());
+        assert!(parse_project("").is_err());
+        assert!(parse_project("-stack").is_err());
+        assert!(parse_project("../stack").is_err());
+        assert!(parse_project("my swarm").is_err());
+    }
+}
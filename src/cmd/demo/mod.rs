@@ -1,15 +1,21 @@
 mod srv;
 
-use crate::images::BtcNode;
+use crate::config::ShutdownConfig;
+use crate::grpc::cln::client::ClnClient;
+use crate::grpc::cln::greenlight::NetAddressType;
+use crate::images::{BtcNode, ClnNode};
 use crate::rocket_utils::*;
-use crate::{dock::*, env, images, logs};
-use anyhow::Result;
+use crate::state::State;
+use crate::utils::{container_name, domain};
+use crate::{dock::*, env, images, logs, swarm};
+use anyhow::{anyhow, Result};
 use base58::ToBase58;
 use bollard::Docker;
 use futures_util::StreamExt;
 use once_cell::sync::Lazy;
 use rocket::tokio;
 use rocket::tokio::sync::{broadcast, mpsc, Mutex};
+use serde_json::json;
 use std::collections::{hash_map::DefaultHasher, HashMap};
 use std::fs::File;
 use std::hash::{Hash, Hasher};
@@ -27,71 +33,176 @@ static NODES: Lazy<HashMap<String, u8>> = Lazy::new(|| {
     n
 });
 
-pub async fn run(docker: Docker) -> Result<()> {
-    let proj = "demo";
+pub async fn run(docker: Docker, proj: &str, port: u16) -> Result<()> {
     let network = "regtest";
 
-    // btc setup
-    let btc_node = BtcNode::new("bitcoind", network, "foo", "bar");
-    let btc1 = images::btc(proj, &btc_node);
-    let btc_id = create_and_start(&docker, btc1).await?;
-    log::info!("created bitcoind");
-
-    // cln setup
+    let shutdown_conf: ShutdownConfig = Default::default();
+    // start order, for the shutdown
+    let mut order = Vec::new();
     let mut id_map = HashMap::new();
     let mut log_txs = logs::new_log_chans();
-    for (tag, i) in NODES.iter() {
-        let name = format!("cln{}", i);
-        let cln1 = images::cln_vls(proj, &name, network, *i as u16, &btc_node);
-        let id = create_and_start(&docker, cln1).await?;
-        id_map.insert(tag, id);
-        // add in default env var $CLN
-        env::add_to_env(tag, "CLN", &format!("lightning-cli --network={}", network)).await;
-        env::add_to_env(tag, "HOST", "host.docker.internal").await;
-        // streaming logs
-        let mut stream = logs_stream(&docker, &name);
-        let (log_tx, _) = broadcast::channel(1000);
-        logs::collect_logs(&tag, log_tx.clone());
-        log_txs.insert(tag.clone(), log_tx.clone());
-        tokio::spawn(async move {
-            while let Some(lg) = stream.next().await {
-                if let Some(msg) = match_stream(lg) {
-                    let _ = log_tx.send(String::from_utf8_lossy(&msg).to_string());
+    let started = swarm::guarded(async {
+        ensure_network(&docker, proj).await?;
+
+        // btc setup
+        let btc_node = BtcNode::new("bitcoind", network, "foo", "bar");
+        let btc1 = images::btc(proj, &btc_node);
+        create_and_start(&docker, btc1).await?;
+        order.push(btc_node.name.clone());
+        log::info!("created bitcoind");
+
+        // cln setup
+        for (tag, i) in NODES.iter() {
+            let name = format!("cln{}", i);
+            let cln1 = images::cln_vls(proj, &name, network, *i as u16, &btc_node);
+            let id = create_and_start(&docker, cln1).await?;
+            order.push(name.clone());
+            id_map.insert(tag, id);
+            // add in default env var $CLN
+            env::add_to_env(tag, "CLN", &format!("lightning-cli --network={}", network)).await;
+            // the demo commands connect to cln3, over the project network
+            env::add_to_env(tag, "HOST", &domain("cln3")).await;
+            // streaming logs
+            let mut stream = logs_stream(&docker, &container_name(proj, &name));
+            let (log_tx, _) = broadcast::channel(1000);
+            logs::collect_logs(tag, log_tx.clone());
+            log_txs.insert(tag.clone(), log_tx.clone());
+            tokio::spawn(async move {
+                while let Some(lg) = stream.next().await {
+                    if let Some(msg) = match_stream(lg) {
+                        let _ = log_tx.send(String::from_utf8_lossy(&msg).to_string());
+                    }
                 }
-            }
-        });
-        log::info!("created {}", name);
+            });
+            log::info!("created {}", name);
+        }
+        Ok(())
+    });
+    if let Err(e) = started.await {
+        log::error!("startup failed: {}", e);
+        swarm::shutdown(&docker, proj, &order, &shutdown_conf).await;
+        return Err(e);
     }
 
-    // commands for "docker exec"
+    // demo commands, over grpc or "docker exec"
     let (tx, mut rx) = mpsc::channel::<CmdRequest>(1000);
     let docker_arc = Arc::new(docker);
     let docker_ = docker_arc.clone();
     let id_map_ = id_map.clone();
+    let proj_ = proj.to_string();
     tokio::spawn(async move {
+        // grpc clients by node name, connected on first use
+        let mut clients = HashMap::new();
         while let Some(cmd) = rx.recv().await {
-            if let Some(node_id) = id_map_.get(&cmd.tag) {
-                if let Ok(ress) = exec(&docker_, &node_id, &cmd.message).await {
-                    let _ = cmd.reply_tx.send(ress);
-                }
+            if let (Some(node_id), Some(idx)) = (id_map_.get(&cmd.tag), NODES.get(&cmd.tag)) {
+                let cln = ClnNode::new(&format!("cln{}", idx), network, *idx as u16, "bitcoind");
+                let ress = run_cmd(&docker_, &proj_, &cln, &mut clients, node_id, &cmd.message)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::warn!("{} failed: {}", cmd.message, e);
+                        e.to_string()
+                    });
+                let _ = cmd.reply_tx.send(ress);
             }
         }
     });
 
     // launch rocket
-    let port = std::env::var("ROCKET_PORT").unwrap_or("8000".to_string());
     log::info!("🚀 => http://localhost:{}", port);
     let log_txs = Arc::new(Mutex::new(log_txs));
-    let _r = srv::launch_rocket(tx.clone(), log_txs).await;
+    if let Err(e) = srv::launch_rocket(port, tx.clone(), log_txs).await {
+        log::error!("rocket failed: {}", e);
+    }
 
     // shutdown containers
-    remove_container(&docker_arc, &btc_id).await?;
-    for (_tag, id) in id_map.iter() {
-        remove_container(&docker_arc, &id).await?;
-    }
+    swarm::shutdown(&docker_arc, proj, &order, &shutdown_conf).await;
     Ok(())
 }
 
+// getinfo and newaddr go over the node's grpc api, the rest through lightning-cli,
+// which is also the fallback when grpc isn't reachable
+async fn run_cmd(
+    docker: &Docker,
+    proj: &str,
+    cln: &ClnNode,
+    clients: &mut HashMap<String, ClnClient>,
+    id: &str,
+    msg: &str,
+) -> Result<String> {
+    let args: Vec<&str> = msg.split_whitespace().collect();
+    let method = match args.as_slice() {
+        ["lightning-cli", flags @ .., m] if flags.iter().all(|f| f.starts_with("--")) => *m,
+        _ => return exec(docker, id, msg).await,
+    };
+    if !matches!(method, "getinfo" | "newaddr") {
+        return exec(docker, id, msg).await;
+    }
+    if !clients.contains_key(&cln.name) {
+        // the demo publishes every port as is, so the default state finds them
+        match ClnClient::new(proj, cln, &State::default()).await {
+            Ok(client) => {
+                clients.insert(cln.name.clone(), client);
+            }
+            Err(e) => {
+                log::warn!(
+                    "{} grpc not reachable, using lightning-cli: {}",
+                    cln.name,
+                    e
+                );
+                return exec(docker, id, msg).await;
+            }
+        }
+    }
+    let client = clients.get_mut(&cln.name).expect("just inserted");
+    match grpc_cmd(client, method).await {
+        Ok(res) => Ok(serde_json::to_string_pretty(&res)?),
+        Err(e) => {
+            // reconnect next time, the node may have restarted
+            log::warn!(
+                "{} grpc {} failed, using lightning-cli: {}",
+                cln.name,
+                method,
+                e
+            );
+            clients.remove(&cln.name);
+            exec(docker, id, msg).await
+        }
+    }
+}
+
+// in the shape of lightning-cli's output, as far as the grpc api has the fields
+async fn grpc_cmd(client: &mut ClnClient, method: &str) -> Result<serde_json::Value> {
+    Ok(match method {
+        "getinfo" => {
+            let info = client.get_info().await?;
+            let address: Vec<serde_json::Value> = info
+                .addresses
+                .iter()
+                .map(|a| {
+                    let kind = NetAddressType::from_i32(a.r#type).unwrap_or(NetAddressType::Ipv4);
+                    json!({
+                        "type": kind.as_str_name().to_lowercase(),
+                        "address": a.addr,
+                        "port": a.port,
+                    })
+                })
+                .collect();
+            json!({
+                "id": hex::encode(info.node_id),
+                "alias": info.alias,
+                "color": hex::encode(info.color),
+                "num_peers": info.num_peers,
+                "address": address,
+                "version": info.version,
+                "blockheight": info.blockheight,
+                "network": info.network,
+            })
+        }
+        "newaddr" => json!({ "bech32": client.new_addr().await? }),
+        _ => return Err(anyhow!("{} is not served over grpc", method)),
+    })
+}
+
 const NODES_FILE_PATH: &str = "src/cmd/demo/app/public/nodes.json";
 fn write_nodes_file(n: &HashMap<String, u8>) {
     let st = serde_json::to_string_pretty(n).expect("failed to make json string");
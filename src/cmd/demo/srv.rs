@@ -15,7 +15,7 @@ pub async fn cmd(sender: &State<mpsc::Sender<CmdRequest>>, tag: &str, txt: &str)
         return Ok("".to_string());
     }
     let (request, reply_rx) = CmdRequest::new(tag, &final_txt);
-    let _ = sender.send(request).await.map_err(|_| Error::Fail)?;
+    sender.send(request).await.map_err(|_| Error::Fail)?;
     let reply = reply_rx.await.map_err(|_| Error::Fail)?;
     Ok(transform_reply(&reply))
 }
@@ -29,13 +29,15 @@ fn transform_reply(reply: &str) -> String {
 }
 
 pub async fn launch_rocket(
+    port: u16,
     tx: mpsc::Sender<CmdRequest>,
     log_txs: Arc<Mutex<LogChans>>,
 ) -> Result<Rocket<Ignite>> {
-    Ok(rocket::build()
+    let figment = rocket::Config::figment().merge(("port", port));
+    Ok(rocket::custom(figment)
         .mount("/", FileServer::from(relative!("src/cmd/demo/app/public")))
         .mount("/api/", routes![cmd, logstream, logs])
-        .attach(CORS)
+        .attach(Cors)
         .manage(tx)
         .manage(log_txs)
         .launch()
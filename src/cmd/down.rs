@@ -1,16 +1,29 @@
 use crate::dock::*;
+use crate::utils::{network_name, NODE_LABEL, ROLE_LABEL};
 use bollard::Docker;
 
-pub async fn run(docker: Docker) -> anyhow::Result<()> {
-    let all = list_containers(&docker).await?;
-    if all.len() == 0 {
-        log::info!("=> no running containers");
+pub async fn run(docker: Docker, project: &str, dry_run: bool) -> anyhow::Result<()> {
+    let all = list_containers(&docker, project).await?;
+    if all.is_empty() {
+        log::info!("=> no containers for project {}", project);
     }
     for c in all {
+        let labels = c.labels.unwrap_or_default();
+        let node = labels.get(NODE_LABEL).cloned().unwrap_or_default();
+        let role = labels.get(ROLE_LABEL).cloned().unwrap_or_default();
+        let state = c.state.unwrap_or_default();
+        if dry_run {
+            log::info!("=> would pull down {} ({}, {})", node, role, state);
+            continue;
+        }
         if let Some(id) = c.id {
-            log::info!("=> pulling down {:?}", c.names.unwrap().get(0).unwrap());
+            log::info!("=> pulling down {} ({})", node, role);
             remove_container(&docker, id.as_str()).await?;
         }
     }
-    Ok(())
+    if dry_run {
+        log::info!("=> would remove network {}", network_name(project));
+        return Ok(());
+    }
+    remove_network(&docker, project).await
 }
@@ -0,0 +1,10 @@
+use crate::dock;
+use anyhow::Result;
+use bollard::Docker;
+
+pub async fn run(docker: Docker, project: &str, node: &str, cmd: &[String]) -> Result<()> {
+    let id = dock::find_container(&docker, project, node).await?;
+    let out = dock::exec_argv(&docker, &id, cmd.to_vec()).await?;
+    print!("{}", out);
+    Ok(())
+}
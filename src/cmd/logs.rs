@@ -0,0 +1,21 @@
+use crate::dock::*;
+use anyhow::Result;
+use bollard::Docker;
+use futures_util::StreamExt;
+
+pub async fn run(docker: Docker, project: &str, node: &str, follow: bool) -> Result<()> {
+    let name = find_container(&docker, project, node).await?;
+    if !follow {
+        for line in container_logs(&docker, &name).await {
+            print!("{}", line);
+        }
+        return Ok(());
+    }
+    let mut stream = logs_stream(&docker, &name);
+    while let Some(lg) = stream.next().await {
+        if let Some(msg) = match_stream(lg) {
+            print!("{}", String::from_utf8_lossy(&msg));
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,8 @@
 pub mod demo;
 pub mod down;
+pub mod exec;
+pub mod logs;
+pub mod rotate;
 pub mod stack;
+pub mod status;
 pub mod test;
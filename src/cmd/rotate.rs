@@ -0,0 +1,116 @@
+use crate::cmd::stack::secrets::{load_secrets, save_secrets, Kind, Secrets};
+use crate::cmd::stack::{nodes, restart_node};
+use crate::config::Config;
+use crate::dock::{list_containers, start_container, stop_container};
+use crate::grpc::lnd::unlocker::{UnlockError, WalletState};
+use crate::images::{LndNode, Node};
+use crate::ready;
+use crate::state::{load_state, State};
+use crate::swarm::{self, Swarm};
+use crate::utils::{container_name, NODE_LABEL};
+use anyhow::{anyhow, Result};
+use bollard::Docker;
+use std::collections::HashSet;
+
+pub async fn run(docker: Docker, project: &str, conf: Config, name: &str) -> Result<()> {
+    let mut secrets = load_secrets(project)?;
+    let state = load_state(project)?;
+    let all = swarm::order(nodes(&conf, &mut secrets)?)?;
+    let node = all
+        .iter()
+        .find(|n| n.name() == name)
+        .ok_or(anyhow!("no node named {}", name))?;
+    if node.is_external() {
+        return Err(anyhow!("{} is external, its secrets live elsewhere", name));
+    }
+    let running = running_nodes(&docker, project).await?;
+    let kind = match node {
+        Node::Btc(_) => Kind::RpcPass,
+        Node::Proxy(_) => Kind::AdminToken,
+        Node::Lnd(l) => {
+            // the wallet itself is re-encrypted, nothing gets recreated
+            if !running.contains(name) {
+                return Err(anyhow!("{} must be running to change its password", name));
+            }
+            return change_password(&docker, project, &conf, &mut secrets, l, &state).await;
+        }
+        _ => return Err(anyhow!("{} has no secret to rotate", name)),
+    };
+    secrets.set(name, kind, &kind.generate());
+    save_secrets(project, &mut secrets)?;
+    log::info!("rotated {:?} of {}", kind, name);
+
+    // recreate the running ones in start order, the rest pick it up on "up"
+    let affected = swarm::dependents(&all, name);
+    let swarm = Swarm {
+        project: project.to_string(),
+        nodes: swarm::order(nodes(&conf, &mut secrets)?)?,
+        state,
+    };
+    for n in swarm.nodes.iter() {
+        if affected.contains(&n.name()) && running.contains(&n.name()) {
+            log::info!("=> recreating {}", n.name());
+            restart_node(&docker, &swarm, &conf, &mut secrets, n).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn running_nodes(docker: &Docker, project: &str) -> Result<HashSet<String>> {
+    let all = list_containers(docker, project).await?;
+    Ok(all
+        .into_iter()
+        .filter(|c| c.state.as_deref() == Some("running"))
+        .filter_map(|c| c.labels.unwrap_or_default().get(NODE_LABEL).cloned())
+        .collect())
+}
+
+async fn change_password(
+    docker: &Docker,
+    project: &str,
+    conf: &Config,
+    secrets: &mut Secrets,
+    lnd: &LndNode,
+    state: &State,
+) -> Result<()> {
+    // ChangePassword is served by the wallet unlocker, so lnd has to be locked
+    if ready::lnd_state(project, lnd, state).await? != WalletState::Locked {
+        log::info!("=> restarting {} to lock its wallet", lnd.name);
+        let id = container_name(project, &lnd.name);
+        stop_container(docker, &id, conf.shutdown.stop_timeout_secs).await?;
+        start_container(docker, &id).await?;
+        ready::retry(&lnd.name, &conf.ready, || async {
+            match ready::lnd_state(project, lnd, state).await? {
+                WalletState::Locked => Ok(()),
+                st => Err(anyhow!("wallet state {:?}", st)),
+            }
+        })
+        .await?;
+    }
+    let unlocker = ready::lnd_unlocker(project, lnd, state).await?;
+    let current = secrets.get_or_generate(&lnd.name, Kind::WalletPass);
+    let new = Kind::WalletPass.generate();
+    // save the new password before lnd re-encrypts the wallet with it,
+    // so it can't get lost between the two
+    secrets.set(&lnd.name, Kind::WalletPass, &new);
+    save_secrets(project, secrets)?;
+    match unlocker.change_password(&current, &new).await {
+        Ok(()) => log::info!("rotated WalletPass of {}", lnd.name),
+        // lnd answered, so the wallet still has the current password
+        Err(e @ (UnlockError::WrongPassword | UnlockError::BadSeed(_) | UnlockError::Lnd(_))) => {
+            secrets.set(&lnd.name, Kind::WalletPass, &current);
+            save_secrets(project, secrets)?;
+            return Err(e.into());
+        }
+        // no answer, the wallet could have either password
+        Err(e) => {
+            log::warn!(
+                "kept the new WalletPass of {}, if it doesn't unlock the previous one was {}",
+                lnd.name,
+                current
+            );
+            return Err(e.into());
+        }
+    }
+    ready::wait_lnd_active(project, lnd, &conf.ready, state).await
+}
@@ -1,68 +1,384 @@
-mod secrets;
+pub mod secrets;
 mod srv;
 
-use crate::grpc::lnd::unlocker::LndUnlocker;
+use crate::config::{Config, Exposure, ReadyConfig, RelayDb};
+use crate::external::{self, Endpoint};
+use crate::grpc::lnd::unlocker::WalletState;
+use crate::images::{self, Node};
+use crate::logs::LogChans;
+use crate::postgres::{self, PostgresConn};
 use crate::rocket_utils::CmdRequest;
-use crate::{dock::*, images, logs};
-use anyhow::Result;
+use crate::state::{bind_ports, load_state, published_ports, save_state, State};
+use crate::utils::set_exposure;
+use crate::{dock::*, logs, ready, swarm};
+use anyhow::{anyhow, Result};
+use bollard::container::Config as DockerConfig;
 use bollard::Docker;
 use rocket::tokio;
+use secrets::{Kind, Secrets};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
-
-pub async fn run(docker: Docker) -> Result<()> {
-    let proj = "stack";
-    let network = "regtest";
-    let secrets = secrets::load_secrets(proj);
-
-    // btc setup
-    let btc_node = images::BtcNode::new("bitcoind", network, "sphinx", &secrets.bitcoind_pass);
-    let btc1 = images::btc(proj, &btc_node);
-    let btc_id = create_and_start(&docker, btc1).await?;
-    log::info!("created bitcoind");
-
-    // lnd setup
-    let http_port = "8881";
-    let lnd_node = images::LndNode::new("lnd1", network, "10009", "/root/.lnd");
-    let lnd1 = images::lnd(proj, &lnd_node, &btc_node, Some(http_port));
-    let lnd_id = create_and_start(&docker, lnd1).await?;
-    log::info!("created LND");
-
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-
-    let cert_path = "vol/stack/lnd1/tls.cert";
-    let unlocker = LndUnlocker::new(http_port, cert_path).await?;
-    let res = unlocker
-        .init_wallet(&secrets.lnd1_password, secrets.lnd1_mnemonic)
-        .await?;
-    log::info!("RES {:?}", res);
-    let _ = unlocker.unlock_wallet(&secrets.lnd1_password).await?;
-
-    let proxy_node = images::ProxyNode::new(
-        "proxy1",
-        network,
-        "11111",
-        "5050",
-        &secrets.proxy_admin_token,
-        &secrets.proxy_store_key,
-    );
-    let proxy1 = images::proxy(proj, &proxy_node, &lnd_node);
-    let proxy_id = create_and_start(&docker, proxy1).await?;
-    log::info!("created PROXY");
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+pub async fn run(docker: Docker, proj: &str, conf: Config, port: u16) -> Result<()> {
+    let mut secrets = secrets::load_secrets(proj)?;
+
+    // resolve the startup order before touching docker
+    let nodes = swarm::order(nodes(&conf, &mut secrets)?)?;
+    if secrets.dirty {
+        secrets::save_secrets(proj, &mut secrets)?;
+    }
+
+    external::check_providers(&conf).await?;
+
+    // pick host ports, reusing the ones from earlier runs
+    let mut state = load_state(proj)?;
+    for node in nodes.iter() {
+        let name = node.name();
+        if node.is_external() {
+            state.ports.remove(&name);
+            continue;
+        }
+        if conf.exposure(&name) == Exposure::Private {
+            match node {
+                Node::Lnd(_) => {
+                    return Err(anyhow!(
+                        "{} can't be private, it is unlocked from the host",
+                        name
+                    ))
+                }
+                Node::Postgres(_) => {
+                    return Err(anyhow!(
+                        "{} can't be private, relay databases are created from the host",
+                        name
+                    ))
+                }
+                _ => (),
+            }
+            state.ports.remove(&name);
+            continue;
+        }
+        let c = container_config(proj, node, &nodes, &state)?;
+        state.allocate(&name, &published_ports(&c), conf.port_offset)?;
+    }
+    save_state(proj, &state)?;
+
+    // one log channel per node, pull progress is streamed there
+    let mut log_txs = logs::new_log_chans();
+    for node in nodes.iter() {
+        let (log_tx, _) = broadcast::channel(1000);
+        logs::collect_logs(&node.name(), log_tx.clone());
+        log_txs.insert(node.name(), log_tx);
+    }
 
     let (tx, _rx) = mpsc::channel::<CmdRequest>(1000);
-    let log_txs = logs::new_log_chans();
 
-    // launch rocket
-    let port = std::env::var("ROCKET_PORT").unwrap_or("8000".to_string());
+    // launch rocket first, so startup progress can be followed in the UI
     log::info!("🚀 => http://localhost:{}", port);
-    let log_txs = Arc::new(Mutex::new(log_txs));
-    let _r = srv::launch_rocket(tx.clone(), log_txs).await;
+    let swarm = Arc::new(swarm::Swarm {
+        project: proj.to_string(),
+        nodes,
+        state,
+    });
+    let shared_log_txs = Arc::new(Mutex::new(log_txs.clone()));
+    let rocket = srv::build_rocket(
+        port,
+        tx.clone(),
+        shared_log_txs,
+        docker.clone(),
+        swarm.clone(),
+    )
+    .ignite()
+    .await?;
+    let rocket_shutdown = rocket.shutdown();
+    let server = tokio::spawn(rocket.launch());
+
+    let order: Vec<String> = swarm.nodes.iter().map(|n| n.name()).collect();
+    ensure_network(&docker, proj).await?;
+    let startup = start_all(&docker, &swarm, &conf, &mut secrets, &log_txs);
+    if let Err(e) = swarm::guarded(startup).await {
+        log::error!("startup failed: {}", e);
+        rocket_shutdown.notify();
+        swarm::shutdown(&docker, proj, &order, &conf.shutdown).await;
+        return Err(e);
+    }
+    log::info!("swarm is up");
+
+    match server.await {
+        Ok(Err(e)) => log::error!("rocket failed: {}", e),
+        Err(e) => log::error!("rocket task failed: {}", e),
+        Ok(Ok(_)) => (),
+    }
 
     // shutdown containers
-    remove_container(&docker, &btc_id).await?;
-    remove_container(&docker, &lnd_id).await?;
-    remove_container(&docker, &proxy_id).await?;
+    swarm::shutdown(&docker, proj, &order, &conf.shutdown).await;
+
+    Ok(())
+}
+
+// on failure, whatever was started is torn down by its project label
+async fn start_all(
+    docker: &Docker,
+    swarm: &swarm::Swarm,
+    conf: &Config,
+    secrets: &mut Secrets,
+    log_txs: &LogChans,
+) -> Result<()> {
+    for node in swarm.nodes.iter() {
+        if node.is_external() {
+            ready_node(docker, swarm, conf, secrets, node, "").await?;
+            continue;
+        }
+        let log_tx = log_txs.get(&node.name());
+        let id = create_node(docker, swarm, conf, node, log_tx).await?;
+        ready_node(docker, swarm, conf, secrets, node, &id).await?;
+    }
+    Ok(())
+}
+
+// create the container, or reuse it if its config did not change
+async fn create_node(
+    docker: &Docker,
+    swarm: &swarm::Swarm,
+    conf: &Config,
+    node: &Node,
+    log_tx: Option<&broadcast::Sender<String>>,
+) -> Result<String> {
+    let proj = swarm.project.as_str();
+    let mut c = container_config(proj, node, &swarm.nodes, &swarm.state)?;
+    if let Some(ports) = swarm.state.ports.get(&node.name()) {
+        bind_ports(&mut c, ports);
+    }
+    set_exposure(&mut c, conf.exposure(&node.name()));
+    let db = match node {
+        Node::Relay(r) => r.db.as_ref().map(|db| (db, &r.postgres)),
+        Node::Tribes(t) => Some((&t.db, &t.postgres)),
+        Node::Meme(m) => Some((&m.db, &m.postgres)),
+        _ => None,
+    };
+    if let Some((db, pg)) = db {
+        postgres::ensure_db(&postgres_admin(swarm, conf, pg)?, db).await?;
+    }
+    // join the tribes container by id, so a recreated tribes recreates the broker too
+    if let Node::Broker(b) = node {
+        let tribes = find_container(docker, proj, &b.tribes).await?;
+        if let Some(hc) = c.host_config.as_mut() {
+            hc.network_mode = Some(format!("container:{}", tribes));
+        }
+    }
+    let image = c.image.clone().unwrap_or_default();
+    pull_image(docker, &image, conf.pull_policy(&image), log_tx).await?;
+    let id = ensure_container(docker, c).await?;
+    log::info!("created {}", node.name());
+    Ok(id)
+}
+
+// wait for the node to come up, unlocking lnd on the way
+async fn ready_node(
+    docker: &Docker,
+    swarm: &swarm::Swarm,
+    conf: &Config,
+    secrets: &mut Secrets,
+    node: &Node,
+    id: &str,
+) -> Result<()> {
+    let proj = swarm.project.as_str();
+    let state = &swarm.state;
+    ready::wait_ready(docker, proj, node, id, &conf.ready, state).await?;
+    if let Node::Lnd(l) = node {
+        unlock(proj, l, secrets, state, &conf.ready).await?;
+        ready::wait_lnd_active(proj, l, &conf.ready, state).await?;
+    }
+    log::info!("{} is ready", node.name());
+    Ok(())
+}
+
+// bring a single node of an existing swarm up to date with its config
+pub async fn restart_node(
+    docker: &Docker,
+    swarm: &swarm::Swarm,
+    conf: &Config,
+    secrets: &mut Secrets,
+    node: &Node,
+) -> Result<()> {
+    let id = create_node(docker, swarm, conf, node, None).await?;
+    ready_node(docker, swarm, conf, secrets, node, &id).await
+}
+
+// the superuser connection to the server holding a node's database,
+// the swarm's own postgres node if there is one, else Config::postgres
+fn postgres_admin(swarm: &swarm::Swarm, conf: &Config, pg: &Option<String>) -> Result<String> {
+    match (pg, &conf.postgres) {
+        (Some(pg), _) => {
+            let pg = swarm::find_postgres(&swarm.nodes, pg)?;
+            Ok(postgres::admin_url(pg, &swarm.state))
+        }
+        (None, Some(url)) => Ok(url.clone()),
+        (None, None) => Err(anyhow!("no postgres configured")),
+    }
+}
+
+// every node gets its own credentials, generated on first use
+pub fn nodes(conf: &Config, secrets: &mut Secrets) -> Result<Vec<Node>> {
+    let network = conf.network.as_str();
+    let btc = "bitcoind";
+    let mut btc_node = match &conf.bitcoind {
+        Some(url) => images::BtcNode::external(btc, network, url)?,
+        None => images::BtcNode::new(
+            btc,
+            network,
+            "sphinx",
+            &secrets.get_or_generate(btc, Kind::RpcPass),
+        ),
+    };
+    btc_node.public = conf.exposure(btc) == Exposure::Public;
+    let mut nodes = vec![Node::Btc(btc_node)];
+    for l in conf.lnds.iter() {
+        secrets.get_or_generate(&l.name, Kind::WalletPass);
+        nodes.push(Node::Lnd(images::LndNode::new(
+            &l.name,
+            network,
+            &l.port,
+            Some(&l.http_port),
+            btc,
+        )));
+    }
+    for p in conf.proxies.iter() {
+        nodes.push(Node::Proxy(images::ProxyNode::new(
+            &p.name,
+            network,
+            &p.port,
+            &p.admin_port,
+            &secrets.get_or_generate(&p.name, Kind::AdminToken),
+            &secrets.get_or_generate(&p.name, Kind::StoreKey),
+            &p.lnd,
+        )));
+    }
+    // tribes and its broker run locally unless an external tribes is configured
+    let local_tribes = conf.tribes.is_none() && !conf.relays.is_empty();
+    // and so does the meme server
+    let local_meme = conf.meme.is_none() && !conf.relays.is_empty();
+    // the swarm runs its own postgres unless an external one is configured
+    let pg = "postgres";
+    let mut pg_url = conf.postgres.clone();
+    let uses_pg =
+        local_tribes || local_meme || conf.relays.iter().any(|r| r.db == RelayDb::Postgres);
+    if pg_url.is_none() && uses_pg {
+        let node = images::PostgresNode::new(pg, &secrets.get_or_generate(pg, Kind::DbPass));
+        pg_url = Some(postgres::internal_url(&node));
+        nodes.push(Node::Postgres(node));
+    }
+    let pg_node = conf.postgres.is_none().then(|| pg.to_string());
+    let mut services = vec![];
+    let (mut tribes, mut mqtt_port) = (None, None);
+    if let Some(url) = pg_url.as_ref().filter(|_| local_tribes) {
+        let (name, port) = ("tribes", 5002);
+        let pass = secrets.get_or_generate(name, Kind::DbPass);
+        let db = PostgresConn::on(url, name, &pass)?;
+        let broker_port = "1883".to_string();
+        let mut node = images::TribesNode::new(name, &port.to_string(), db);
+        node.postgres = pg_node.clone();
+        node.broker_port = Some(broker_port.clone());
+        nodes.push(Node::Tribes(node));
+        nodes.push(Node::Broker(images::BrokerNode::new(
+            "mqtt",
+            &broker_port,
+            name,
+        )));
+        tribes = Some(Endpoint::internal(name, port));
+        mqtt_port = Some(broker_port);
+        services.extend([name.to_string(), "mqtt".to_string()]);
+    }
+    if let Some(url) = &conf.tribes {
+        tribes = Some(Endpoint::parse(url, None)?);
+    }
+    let mut meme = None;
+    if let Some(url) = pg_url.as_ref().filter(|_| local_meme) {
+        let (name, port) = ("meme", 5555);
+        let pass = secrets.get_or_generate(name, Kind::DbPass);
+        let db = PostgresConn::on(url, name, &pass)?;
+        let mut node = images::MemeNode::new(name, &port.to_string(), db);
+        node.postgres = pg_node.clone();
+        nodes.push(Node::Meme(node));
+        meme = Some(Endpoint::internal(name, port));
+        services.push(name.to_string());
+    }
+    if let Some(url) = &conf.meme {
+        meme = Some(Endpoint::parse(url, None)?);
+    }
+    for r in conf.relays.iter() {
+        let mut relay = images::RelayNode::new(&r.name, &r.port, &r.proxy);
+        relay.tribes = tribes.clone();
+        relay.mqtt_port = mqtt_port.clone();
+        relay.meme = meme.clone();
+        relay.services = services.clone();
+        if let (RelayDb::Postgres, Some(url)) = (r.db, &pg_url) {
+            let pass = secrets.get_or_generate(&r.name, Kind::DbPass);
+            relay.db = Some(PostgresConn::on(url, &r.name, &pass)?);
+            relay.postgres = pg_node.clone();
+        }
+        nodes.push(Node::Relay(relay));
+    }
+    for c in conf.clns.iter() {
+        nodes.push(Node::Cln(images::ClnNode::new(
+            &c.name, network, c.idx, btc,
+        )));
+    }
+    Ok(nodes)
+}
+
+fn container_config(
+    proj: &str,
+    node: &Node,
+    nodes: &[Node],
+    state: &State,
+) -> Result<DockerConfig<String>> {
+    Ok(match node {
+        Node::Btc(b) => images::btc(proj, b),
+        Node::Lnd(l) => {
+            let btc = swarm::find_btc(nodes, &l.btc)?;
+            images::lnd(proj, l, btc)
+        }
+        Node::Proxy(p) => {
+            let lnd = swarm::find_lnd(nodes, &p.lnd)?;
+            images::proxy(proj, p, lnd)
+        }
+        Node::Cln(c) => {
+            let btc = swarm::find_btc(nodes, &c.btc)?;
+            images::cln_vls(proj, &c.name, &c.network, c.idx, btc)
+        }
+        Node::Relay(r) => {
+            let proxy = swarm::find_proxy(nodes, &r.proxy)?;
+            let lnd = swarm::find_lnd(nodes, &proxy.lnd)?;
+            let host_port = state.host_port(&r.name, &r.port);
+            images::relay(proj, r, lnd, proxy, &host_port)
+        }
+        Node::Postgres(p) => images::postgres(proj, p),
+        Node::Tribes(t) => images::tribes(proj, t),
+        Node::Broker(b) => images::broker(proj, b),
+        Node::Meme(m) => images::meme(proj, m),
+    })
+}
 
+async fn unlock(
+    proj: &str,
+    lnd: &images::LndNode,
+    secrets: &mut Secrets,
+    state: &State,
+    conf: &ReadyConfig,
+) -> Result<()> {
+    let unlocker = ready::lnd_unlocker(proj, lnd, state).await?;
+    // a new wallet gets its seed from lnd itself, saved before it is used
+    let st = unlocker.wait_for_state(conf).await?;
+    if st == WalletState::NonExisting && secrets.mnemonic(&lnd.name).is_empty() {
+        secrets.set_mnemonic(&lnd.name, unlocker.gen_seed().await?);
+        secrets::save_secrets(proj, secrets)?;
+    }
+    let password = secrets.get_or_generate(&lnd.name, Kind::WalletPass);
+    let mnemonic = secrets.mnemonic(&lnd.name);
+    match unlocker.ensure_unlocked(&password, &mnemonic, conf).await? {
+        WalletState::NonExisting => log::info!("{} wallet created", lnd.name),
+        WalletState::Locked => log::info!("{} wallet unlocked", lnd.name),
+        _ => (),
+    }
     Ok(())
 }
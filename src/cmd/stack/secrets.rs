@@ -1,47 +1,275 @@
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng, RngCore};
 use serde::{Deserialize, Serialize};
-use std::fs::{self, File};
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // bitcoind rpc password
+    RpcPass,
+    // lnd wallet password
+    WalletPass,
+    // proxy admin token
+    AdminToken,
+    // proxy store key
+    StoreKey,
+    // postgres superuser, or a relay's database user
+    DbPass,
+}
+
+impl Kind {
+    pub fn generate(&self) -> String {
+        match self {
+            Kind::RpcPass | Kind::WalletPass | Kind::DbPass => random_word(32),
+            Kind::AdminToken => hex::encode(random_bytes(32)),
+            // store key is 16 bytes to hex
+            Kind::StoreKey => hex::encode_upper(random_bytes(16)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Secrets {
-    pub bitcoind_pass: String,
-    pub lnd1_mnemonic: Vec<String>,
-    pub lnd1_password: String,
-    pub proxy_admin_token: String,
-    pub proxy_store_key: String,
-}
-
-fn random_secrets() -> Secrets {
-    let mnemonic = vec![
-        "above", "hair", "trigger", "live", "innocent", "monster", "surprise", "discover", "art",
-        "broccoli", "cable", "balcony", "exclude", "maple", "luggage", "dragon", "erosion",
-        "basic", "census", "earn", "ripple", "gossip", "record", "monster",
-    ];
-    // store key is 16 bytes to hex
-    Secrets {
-        bitcoind_pass: "asdfasdf".to_string(),
-        lnd1_mnemonic: mnemonic.iter().map(|s| s.to_string()).collect(),
-        lnd1_password: "asdfasdf".to_string(),
-        proxy_admin_token: "asdfasdf".to_string(),
-        proxy_store_key: "4967BC837D7EFF47B4BC890328F5A495".to_string(),
-    }
-}
-
-pub fn load_secrets(project: &str) -> Secrets {
-    let path = format!("vol/{}/secrets.json", project);
-    let rs = random_secrets();
-    match fs::read(path.clone()) {
-        Ok(data) => match serde_json::from_slice(&data) {
-            Ok(d) => d,
-            Err(_) => rs,
-        },
-        Err(_e) => {
-            let st = serde_json::to_string_pretty(&rs).expect("failed to make json string");
-            let mut file = File::create(path).expect("create failed");
-            file.write_all(st.as_bytes()).expect("write failed");
-            rs
+    // {node: {kind: secret}}
+    pub nodes: BTreeMap<String, BTreeMap<Kind, String>>,
+    // lnd seeds, a node has none until lnd has generated it
+    #[serde(default)]
+    pub mnemonics: BTreeMap<String, Vec<String>>,
+    // something was generated since the last save
+    #[serde(skip)]
+    pub dirty: bool,
+}
+
+impl Secrets {
+    pub fn get(&self, node: &str, kind: Kind) -> Option<&String> {
+        self.nodes.get(node).and_then(|n| n.get(&kind))
+    }
+    pub fn set(&mut self, node: &str, kind: Kind, secret: &str) {
+        let n = self.nodes.entry(node.to_string()).or_default();
+        n.insert(kind, secret.to_string());
+        self.dirty = true;
+    }
+    pub fn get_or_generate(&mut self, node: &str, kind: Kind) -> String {
+        if let Some(s) = self.get(node, kind) {
+            return s.clone();
+        }
+        let secret = kind.generate();
+        self.set(node, kind, &secret);
+        secret
+    }
+    pub fn mnemonic(&self, node: &str) -> Vec<String> {
+        self.mnemonics.get(node).cloned().unwrap_or_default()
+    }
+    pub fn set_mnemonic(&mut self, node: &str, words: Vec<String>) {
+        self.mnemonics.insert(node.to_string(), words);
+        self.dirty = true;
+    }
+}
+
+// secrets.json from before the per node store, for the default config
+#[derive(Deserialize)]
+struct Legacy {
+    bitcoind_pass: String,
+    lnd1_mnemonic: Vec<String>,
+    lnd1_password: String,
+    proxy_admin_token: String,
+    proxy_store_key: String,
+}
+
+impl From<Legacy> for Secrets {
+    fn from(l: Legacy) -> Self {
+        let mut s = Secrets::default();
+        s.set("bitcoind", Kind::RpcPass, &l.bitcoind_pass);
+        s.set("lnd1", Kind::WalletPass, &l.lnd1_password);
+        s.set("proxy1", Kind::AdminToken, &l.proxy_admin_token);
+        s.set("proxy1", Kind::StoreKey, &l.proxy_store_key);
+        if !l.lnd1_mnemonic.is_empty() {
+            s.set_mnemonic("lnd1", l.lnd1_mnemonic);
+        }
+        s
+    }
+}
+
+fn parse(path: &str, data: &[u8]) -> Result<Secrets> {
+    if let Ok(legacy) = serde_json::from_slice::<Legacy>(data) {
+        log::info!("converting {} to per node secrets", path);
+        return Ok(legacy.into());
+    }
+    serde_json::from_slice(data).map_err(|e| anyhow!("bad {}: {}", path, e))
+}
+
+pub fn random_word(n: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(n)
+        .map(char::from)
+        .collect()
+}
+
+pub fn random_bytes(n: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; n];
+    thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+fn secrets_path(project: &str) -> String {
+    format!("vol/{}/secrets.json", project)
+}
+
+// setting either of these encrypts secrets.json at rest
+pub const PASSPHRASE_ENV: &str = "SWARM_SECRETS_PASSPHRASE";
+pub const KEY_FILE_ENV: &str = "SWARM_SECRETS_KEY_FILE";
+
+// scrypt cost for new files, 2^15 like scrypt's recommended params
+const SCRYPT_LOG_N: u8 = 15;
+
+// an encrypted secrets.json
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    // scrypt params
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn passphrase() -> Result<Option<String>> {
+    if let Ok(pass) = std::env::var(PASSPHRASE_ENV) {
+        if !pass.is_empty() {
+            return Ok(Some(pass));
         }
     }
+    match std::env::var(KEY_FILE_ENV) {
+        Ok(path) => {
+            let key = fs::read_to_string(&path)
+                .map_err(|e| anyhow!("can't read key file {}: {}", path, e))?;
+            Ok(Some(key.trim().to_string()))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+fn cipher(pass: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<ChaCha20Poly1305> {
+    let params = scrypt::Params::new(log_n, r, p).map_err(|e| anyhow!("scrypt: {}", e))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(pass.as_bytes(), salt, &params, &mut key)
+        .map_err(|e| anyhow!("scrypt: {}", e))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
 }
 
-pub fn add_to_secrets() {}
+fn seal(data: &[u8], pass: &str, log_n: u8) -> Result<Sealed> {
+    let (r, p) = (8, 1);
+    let salt = random_bytes(16);
+    let nonce = random_bytes(12);
+    let ciphertext = cipher(pass, &salt, log_n, r, p)?
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|_| anyhow!("failed to encrypt secrets"))?;
+    Ok(Sealed {
+        log_n,
+        r,
+        p,
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: base64::encode(ciphertext),
+    })
+}
+
+fn open(sealed: &Sealed, pass: &str) -> Result<Vec<u8>> {
+    let salt = hex::decode(&sealed.salt)?;
+    let nonce = hex::decode(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(anyhow!("bad nonce length {}", nonce.len()));
+    }
+    let ciphertext = base64::decode(&sealed.ciphertext)?;
+    cipher(pass, &salt, sealed.log_n, sealed.r, sealed.p)?
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow!("can't decrypt secrets, wrong passphrase?"))
+}
+
+// empty on the first run, secrets are generated as nodes ask for them
+pub fn load_secrets(project: &str) -> Result<Secrets> {
+    let path = secrets_path(project);
+    let pass = passphrase()?;
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(_) => return Ok(Default::default()),
+    };
+    if let Ok(sealed) = serde_json::from_slice::<Sealed>(&data) {
+        let pass = pass.ok_or(anyhow!(
+            "{} is encrypted, set {} or {}",
+            path,
+            PASSPHRASE_ENV,
+            KEY_FILE_ENV
+        ))?;
+        return parse(&path, &open(&sealed, &pass)?);
+    }
+    let mut secrets = parse(&path, &data)?;
+    // a passphrase was set on an existing plaintext file
+    secrets.dirty |= pass.is_some();
+    Ok(secrets)
+}
+
+pub fn save_secrets(project: &str, secrets: &mut Secrets) -> Result<()> {
+    fs::create_dir_all(format!("vol/{}", project))?;
+    let plain = serde_json::to_string_pretty(secrets)?;
+    let st = match passphrase()? {
+        Some(pass) => serde_json::to_string_pretty(&seal(plain.as_bytes(), &pass, SCRYPT_LOG_N)?)?,
+        None => plain,
+    };
+    // only readable by the owner, also when the file was created before with looser permissions
+    let path = secrets_path(project);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(st.as_bytes())?;
+    secrets.dirty = false;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_or_generate() {
+        let mut s = Secrets::default();
+        let a = s.get_or_generate("lnd1", Kind::WalletPass);
+        let b = s.get_or_generate("lnd2", Kind::WalletPass);
+        assert_ne!(a, b);
+        assert_eq!(s.get_or_generate("lnd1", Kind::WalletPass), a);
+        assert_eq!(s.get_or_generate("proxy1", Kind::AdminToken).len(), 64);
+        let key = s.get_or_generate("proxy1", Kind::StoreKey);
+        assert_eq!(hex::decode(key).unwrap().len(), 16);
+        assert!(s.mnemonic("lnd1").is_empty());
+        assert!(s.dirty);
+    }
+
+    #[test]
+    fn test_legacy() {
+        let data = r#"{"bitcoind_pass":"b","lnd1_mnemonic":["above","hair"],"lnd1_password":"l","proxy_admin_token":"t","proxy_store_key":"k"}"#;
+        let s = parse("secrets.json", data.as_bytes()).unwrap();
+        assert_eq!(s.get("bitcoind", Kind::RpcPass).unwrap(), "b");
+        assert_eq!(s.get("proxy1", Kind::StoreKey).unwrap(), "k");
+        assert_eq!(s.mnemonic("lnd1"), vec!["above", "hair"]);
+    }
+
+    #[test]
+    fn test_seal() {
+        let sealed = seal(b"seed words", "hunter2", 4).unwrap();
+        assert_eq!(open(&sealed, "hunter2").unwrap(), b"seed words");
+        assert!(open(&sealed, "hunter3").is_err());
+    }
+}
@@ -1,21 +1,27 @@
 use crate::logs::LogChans;
 use crate::rocket_utils::*;
-use crate::routes::{cmd, logs, logstream};
+use crate::routes::{cmd, logs, logstream, ports, status};
+use crate::swarm::Swarm;
+use bollard::Docker;
 use fs::{relative, FileServer};
 use rocket::*;
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
 
-pub async fn launch_rocket(
+pub fn build_rocket(
+    port: u16,
     tx: mpsc::Sender<CmdRequest>,
     log_txs: Arc<Mutex<LogChans>>,
-) -> Result<Rocket<Ignite>> {
-    Ok(rocket::build()
+    docker: Docker,
+    swarm: Arc<Swarm>,
+) -> Rocket<Build> {
+    let figment = rocket::Config::figment().merge(("port", port));
+    rocket::custom(figment)
         .mount("/", FileServer::from(relative!("src/cmd/stack/app/public")))
-        .mount("/api/", routes![cmd, logs, logstream])
-        .attach(CORS)
+        .mount("/api/", routes![cmd, logs, logstream, ports, status])
+        .attach(Cors)
         .manage(tx)
         .manage(log_txs)
-        .launch()
-        .await?)
+        .manage(docker)
+        .manage(swarm)
 }
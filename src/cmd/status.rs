@@ -0,0 +1,36 @@
+use crate::cmd::stack::{nodes, secrets};
+use crate::config::Config;
+use crate::state::load_state;
+use crate::status::project_status;
+use anyhow::Result;
+use bollard::Docker;
+
+pub async fn run(docker: Docker, project: &str, conf: Config) -> Result<()> {
+    let mut secrets = secrets::load_secrets(project)?;
+    let nodes = nodes(&conf, &mut secrets)?;
+    let state = load_state(project)?;
+    let all = project_status(&docker, project, &nodes, &state).await?;
+    println!(
+        "{:<10} {:<9} {:<8} {:<40} {:<24} {:<20} HEALTH",
+        "NODE", "ROLE", "STATE", "IMAGE", "PORTS", "UPTIME"
+    );
+    for st in all {
+        let health: Vec<String> = st
+            .health
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        let ports = st.ports.join(",");
+        println!(
+            "{:<10} {:<9} {:<8} {:<40} {:<24} {:<20} {}",
+            st.name,
+            st.role,
+            st.state,
+            st.image,
+            ports,
+            st.uptime,
+            health.join(" ")
+        );
+    }
+    Ok(())
+}
@@ -3,12 +3,13 @@ use crate::images;
 use anyhow::Result;
 use bollard::Docker;
 
-pub async fn run(docker: Docker) -> Result<()> {
+pub async fn run(docker: Docker, proj: &str) -> Result<()> {
     let btc_node = images::BtcNode::new("bitcoind", "regtest", "foo", "bar");
-    let btc1 = images::btc("test", &btc_node);
-    let _id = create_and_start(&docker, btc1).await?;
+    let btc1 = images::btc(proj, &btc_node);
+    ensure_network(&docker, proj).await?;
+    let id = create_and_start(&docker, btc1).await?;
     log::info!("created bitcoind");
-    let logs = container_logs(&docker, "bitcoind").await;
+    let logs = container_logs(&docker, &id).await;
     log::info!("LOGS {:?}", logs);
     Ok(())
 }
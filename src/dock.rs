@@ -1,44 +1,165 @@
-use anyhow::Result;
+use crate::config::PullPolicy;
+use crate::utils::{
+    container_name, custom_network, network_name, HASH_LABEL, NODE_LABEL, PROJECT_LABEL,
+};
+use anyhow::{anyhow, Result};
 use bollard::container::Config;
-use bollard::container::{CreateContainerOptions, LogOutput, LogsOptions, RemoveContainerOptions};
+use bollard::container::{
+    CreateContainerOptions, ListContainersOptions, LogOutput, LogsOptions, RemoveContainerOptions,
+    StopContainerOptions,
+};
 use bollard::exec::{CreateExecOptions, StartExecResults};
 use bollard::image::CreateImageOptions;
+use bollard::network::InspectNetworkOptions;
 use bollard::service::ContainerSummary;
 use bollard::Docker;
-use futures_util::{Stream, StreamExt, TryStreamExt};
+use futures_util::{Stream, StreamExt};
 use rocket::tokio;
+use rocket::tokio::sync::broadcast;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 
-pub fn er() -> Docker {
-    Docker::connect_with_socket_defaults().unwrap()
+pub fn er() -> Result<Docker> {
+    Ok(Docker::connect_with_socket_defaults()?)
 }
 
 pub async fn create_and_start(docker: &Docker, c: Config<String>) -> Result<String> {
-    // if it contains a "/" its from the registry
-    if c.image.clone().unwrap().contains("/") {
-        create_image(&docker, &c).await?;
-    }
-    let id = create_container(&docker, c).await?;
-    start_container(&docker, &id).await?;
+    pull_image(
+        docker,
+        &c.image.clone().unwrap(),
+        PullPolicy::IfMissing,
+        None,
+    )
+    .await?;
+    let id = create_container(docker, c).await?;
+    start_container(docker, &id).await?;
     Ok(id)
 }
 
-pub async fn create_image(docker: &Docker, c: &Config<String>) -> Result<()> {
-    docker
-        .create_image::<String>(
-            Some(CreateImageOptions {
-                from_image: c.image.clone().unwrap().into(),
-                ..Default::default()
-            }),
-            None,
-            None,
-        )
-        .try_collect::<Vec<_>>()
-        .await?;
+// reuse a container with the same name if its image and config match,
+// recreate it if they differ, and start it if it was stopped
+pub async fn ensure_container(docker: &Docker, mut c: Config<String>) -> Result<String> {
+    let name = name_of(&c)?;
+    let hash = config_hash(&c)?;
+    let project = c
+        .labels
+        .as_ref()
+        .and_then(|ls| ls.get(PROJECT_LABEL))
+        .cloned();
+    c.labels
+        .get_or_insert_with(HashMap::new)
+        .insert(HASH_LABEL.to_string(), hash.clone());
+    match docker.inspect_container(&name, None).await {
+        Ok(existing) => {
+            let id = existing.id.clone().unwrap_or_default();
+            let labels = existing.config.and_then(|c| c.labels).unwrap_or_default();
+            // never reuse or remove a container of another project
+            if labels.get(PROJECT_LABEL) != project.as_ref() {
+                return Err(anyhow!(
+                    "container {} belongs to project {}, not {}",
+                    name,
+                    labels.get(PROJECT_LABEL).map_or("none", |p| p.as_str()),
+                    project.as_deref().unwrap_or("none")
+                ));
+            }
+            let image_id = match docker.inspect_image(&c.image.clone().unwrap()).await {
+                Ok(img) => img.id,
+                Err(_) => None,
+            };
+            if labels.get(HASH_LABEL) == Some(&hash) && existing.image == image_id {
+                let running = existing.state.and_then(|s| s.running).unwrap_or(false);
+                if !running {
+                    log::info!("=> starting existing {}", name);
+                    start_container(docker, &id).await?;
+                } else {
+                    log::info!("=> reusing {}", name);
+                }
+                return Ok(id);
+            }
+            log::info!("=> {} changed, recreating", name);
+            remove_container(docker, &id).await?;
+        }
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => (),
+        Err(e) => return Err(e.into()),
+    }
+    create_and_start(docker, c).await
+}
+
+// "{project}-{node}", from the labels every image config carries
+fn name_of(c: &Config<String>) -> Result<String> {
+    let labels = c.labels.clone().unwrap_or_default();
+    match (labels.get(PROJECT_LABEL), labels.get(NODE_LABEL)) {
+        (Some(project), Some(node)) => Ok(container_name(project, node)),
+        _ => Err(anyhow!("container config has no project or node label")),
+    }
+}
+
+// stable across runs and rust releases: serde_json::Value sorts the keys of every map
+fn config_hash(c: &Config<String>) -> Result<String> {
+    let st = serde_json::to_value(c)?.to_string();
+    Ok(hex::encode(Sha256::digest(st.as_bytes())))
+}
+
+// pull according to the policy, logging each layer as its status changes
+// and sending every progress update to "log_tx" for the web UI
+pub async fn pull_image(
+    docker: &Docker,
+    image: &str,
+    policy: PullPolicy,
+    log_tx: Option<&broadcast::Sender<String>>,
+) -> Result<()> {
+    let exists = docker.inspect_image(image).await.is_ok();
+    match policy {
+        PullPolicy::Never if !exists => {
+            return Err(anyhow!(
+                "image {} is missing and pull policy is never",
+                image
+            ))
+        }
+        PullPolicy::Never | PullPolicy::IfMissing if exists => return Ok(()),
+        _ => (),
+    }
+    log::info!("=> pulling {}", image);
+    let mut stream = docker.create_image::<String>(
+        Some(CreateImageOptions {
+            from_image: image.to_string(),
+            ..Default::default()
+        }),
+        None,
+        None,
+    );
+    let mut layers: HashMap<String, String> = HashMap::new();
+    while let Some(info) = stream.next().await {
+        let info = info?;
+        if let Some(err) = info.error {
+            return Err(anyhow!("pull {} failed: {}", image, err));
+        }
+        let status = info.status.unwrap_or_default();
+        let line = match &info.id {
+            Some(layer) => format!(
+                "{}: {} {}",
+                layer,
+                status,
+                info.progress.unwrap_or_default()
+            ),
+            None => status.clone(),
+        };
+        if let Some(tx) = log_tx {
+            let _ = tx.send(line.trim_end().to_string());
+        }
+        let layer = info.id.unwrap_or_default();
+        if layers.get(&layer) != Some(&status) {
+            log::info!("=> {} {} {}", image, layer, status);
+            layers.insert(layer, status);
+        }
+    }
     Ok(())
 }
 
 pub async fn create_container(docker: &Docker, c: Config<String>) -> Result<String> {
-    let name: String = c.hostname.clone().unwrap().into();
+    let name = name_of(&c)?;
     let create_opts = CreateContainerOptions { name };
     let id = docker
         .create_container::<String, String>(Some(create_opts), c)
@@ -51,8 +172,68 @@ pub async fn start_container(docker: &Docker, id: &str) -> Result<()> {
     Ok(docker.start_container::<String>(id, None).await?)
 }
 
-pub async fn list_containers(docker: &Docker) -> Result<Vec<ContainerSummary>> {
-    Ok(docker.list_containers::<String>(None).await?)
+// all containers (running or not) labelled with this project
+pub async fn list_containers(docker: &Docker, project: &str) -> Result<Vec<ContainerSummary>> {
+    let mut filters = HashMap::new();
+    filters.insert(
+        "label".to_string(),
+        vec![format!("{}={}", PROJECT_LABEL, project)],
+    );
+    let opts = ListContainersOptions {
+        all: true,
+        filters,
+        ..Default::default()
+    };
+    Ok(docker.list_containers(Some(opts)).await?)
+}
+
+// id of the container of a node in this project
+pub async fn find_container(docker: &Docker, project: &str, node: &str) -> Result<String> {
+    list_containers(docker, project)
+        .await?
+        .into_iter()
+        .find(|c| {
+            let labels = c.labels.clone().unwrap_or_default();
+            labels.get(NODE_LABEL).map(|n| n.as_str()) == Some(node)
+        })
+        .and_then(|c| c.id)
+        .ok_or(anyhow!(
+            "no container for node {} in project {}",
+            node,
+            project
+        ))
+}
+
+// create the project network unless it already exists
+pub async fn ensure_network(docker: &Docker, project: &str) -> Result<String> {
+    let name = network_name(project);
+    let opts: Option<InspectNetworkOptions<String>> = None;
+    if docker.inspect_network(&name, opts).await.is_ok() {
+        return Ok(name);
+    }
+    log::info!("=> creating network {}", name);
+    docker.create_network(custom_network(project)).await?;
+    Ok(name)
+}
+
+pub async fn remove_network(docker: &Docker, project: &str) -> Result<()> {
+    let name = network_name(project);
+    match docker.remove_network(&name).await {
+        Ok(_) => log::info!("=> removed network {}", name),
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => (),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+// give the container "secs" to exit before it is killed
+pub async fn stop_container(docker: &Docker, id: &str, secs: i64) -> Result<()> {
+    docker
+        .stop_container(id, Some(StopContainerOptions { t: secs }))
+        .await?;
+    Ok(())
 }
 
 pub async fn remove_container(docker: &Docker, id: &str) -> Result<()> {
@@ -114,15 +295,25 @@ pub fn match_stream(log_output: Result<LogOutput, bollard::errors::Error>) -> Op
     }
 }
 
+// a command line without quoting, split on spaces
 pub async fn exec(docker: &Docker, id: &str, cmd: &str) -> Result<String> {
-    let txts = cmd.split(" ").filter(|t| t.len() > 0).collect();
+    let argv = cmd
+        .split(' ')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect();
+    exec_argv(docker, id, argv).await
+}
+
+// arguments are passed on as they are, spaces and all
+pub async fn exec_argv(docker: &Docker, id: &str, argv: Vec<String>) -> Result<String> {
     let exec = docker
         .create_exec(
             id,
             CreateExecOptions {
                 attach_stdout: Some(true),
                 attach_stderr: Some(true),
-                cmd: Some(txts),
+                cmd: Some(argv),
                 ..Default::default()
             },
         )
@@ -130,7 +321,6 @@ pub async fn exec(docker: &Docker, id: &str, cmd: &str) -> Result<String> {
         .id;
     let started = docker.start_exec(&exec, None).await?;
     let mut ret = Vec::new();
-    sleep(400).await;
     if let StartExecResults::Attached { mut output, .. } = started {
         while let Some(Ok(msg)) = output.next().await {
             ret.push(msg.to_string());
@@ -138,9 +328,41 @@ pub async fn exec(docker: &Docker, id: &str, cmd: &str) -> Result<String> {
     } else {
         unreachable!();
     }
-    Ok(ret.join("/n"))
+    Ok(ret.concat())
 }
 
 pub async fn sleep(millis: u64) {
     tokio::time::sleep(tokio::time::Duration::from_millis(millis)).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::labels;
+    #[test]
+    fn test_config_hash() {
+        let c = |keys: &[&str]| Config::<String> {
+            image: Some("img:1".to_string()),
+            labels: Some(
+                keys.iter()
+                    .map(|k| (k.to_string(), k.to_string()))
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let a = config_hash(&c(&["a", "b", "c", "d"])).unwrap();
+        let b = config_hash(&c(&["d", "c", "b", "a"])).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, config_hash(&c(&["a"])).unwrap());
+    }
+
+    #[test]
+    fn test_name_of() {
+        let c = Config::<String> {
+            labels: labels("proj", "lnd", "lnd1"),
+            ..Default::default()
+        };
+        assert_eq!(name_of(&c).unwrap(), "proj-lnd1");
+        assert!(name_of(&Config::<String>::default()).is_err());
+    }
+}
@@ -47,7 +47,7 @@ pub async fn check_env(tag: &str, txt: &str) -> (String, bool) {
                 let var = txts.get(1).unwrap();
                 let vars: Vec<&str> = var.split("=").collect();
                 if vars.len() == 2 {
-                    let key = vars.get(0).unwrap();
+                    let key = vars.first().unwrap();
                     let value = vars.get(1).unwrap();
                     kv = Some((key, value));
                 }
@@ -74,8 +74,8 @@ pub async fn check_env(tag: &str, txt: &str) -> (String, bool) {
                 } else {
                     t // regular word
                 };
-                ft.push_str(&word);
-                ft.push_str(" ");
+                ft.push_str(word);
+                ft.push(' ');
             }
             ret = ft;
         }
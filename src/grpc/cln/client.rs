@@ -0,0 +1,88 @@
+use super::greenlight::amount::Unit;
+use super::greenlight::node_client::NodeClient;
+use super::greenlight::{
+    Amount, FundChannelRequest, FundChannelResponse, GetInfoRequest, GetInfoResponse,
+    IncomingPayment, KeysendRequest, NewAddrRequest, PayRequest, Payment, StreamIncomingFilter,
+};
+use crate::images::{vls_ports, ClnNode};
+use crate::state::State;
+use anyhow::Result;
+use tonic::codec::Streaming;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+// the greenlight Node service of a cln-vls node, on its published grpc port
+pub struct ClnClient {
+    client: NodeClient<Channel>,
+}
+
+impl ClnClient {
+    // mTLS with the certs the node writes into its network dir
+    pub async fn new(proj: &str, cln: &ClnNode, state: &State) -> Result<Self> {
+        let dir = format!("vol/{}/{}/{}", proj, cln.name, cln.network);
+        let ca = std::fs::read(format!("{}/ca.pem", dir))?;
+        let cert = std::fs::read(format!("{}/client.pem", dir))?;
+        let key = std::fs::read(format!("{}/client-key.pem", dir))?;
+        let tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(ca))
+            .identity(Identity::from_pem(cert, key))
+            .domain_name("localhost");
+        let port = state.host_port(&cln.name, &vls_ports(cln.idx).grpc);
+        let channel = Endpoint::from_shared(format!("https://localhost:{}", port))?
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(Self {
+            client: NodeClient::new(channel),
+        })
+    }
+    pub async fn get_info(&mut self) -> Result<GetInfoResponse> {
+        let res = self.client.get_info(GetInfoRequest {}).await?;
+        Ok(res.into_inner())
+    }
+    // a bech32 address
+    pub async fn new_addr(&mut self) -> Result<String> {
+        let res = self.client.new_addr(NewAddrRequest::default()).await?;
+        Ok(res.into_inner().address)
+    }
+}
+
+#[allow(dead_code)]
+impl ClnClient {
+    pub async fn fund_channel(&mut self, pubkey: &str, sats: u64) -> Result<FundChannelResponse> {
+        let req = FundChannelRequest {
+            node_id: hex::decode(pubkey)?,
+            amount: Some(sat_amount(sats)),
+            announce: true,
+            ..Default::default()
+        };
+        let res = self.client.fund_channel(req).await?;
+        Ok(res.into_inner())
+    }
+    pub async fn pay(&mut self, bolt11: &str) -> Result<Payment> {
+        let req = PayRequest {
+            bolt11: bolt11.to_string(),
+            ..Default::default()
+        };
+        let res = self.client.pay(req).await?;
+        Ok(res.into_inner())
+    }
+    pub async fn keysend(&mut self, pubkey: &str, sats: u64) -> Result<Payment> {
+        let req = KeysendRequest {
+            node_id: hex::decode(pubkey)?,
+            amount: Some(sat_amount(sats)),
+            ..Default::default()
+        };
+        let res = self.client.keysend(req).await?;
+        Ok(res.into_inner())
+    }
+    pub async fn stream_incoming(&mut self) -> Result<Streaming<IncomingPayment>> {
+        let res = self.client.stream_incoming(StreamIncomingFilter {}).await?;
+        Ok(res.into_inner())
+    }
+}
+
+fn sat_amount(sats: u64) -> Amount {
+    Amount {
+        unit: Some(Unit::Satoshi(sats)),
+    }
+}
@@ -0,0 +1,11 @@
+pub mod client;
+
+// generated from the protos in proto/, only partly used
+#[allow(dead_code, clippy::all)]
+pub mod greenlight {
+    tonic::include_proto!("greenlight");
+}
+#[allow(dead_code, clippy::all)]
+pub mod scheduler {
+    tonic::include_proto!("scheduler");
+}
@@ -0,0 +1,135 @@
+use super::lnrpc::lightning_client::LightningClient;
+use super::lnrpc::{
+    AddInvoiceResponse, AddressType, Channel, ChannelPoint, ConnectPeerRequest,
+    ConnectPeerResponse, GetInfoRequest, GetInfoResponse, Invoice, LightningAddress,
+    ListChannelsRequest, NewAddressRequest, OpenChannelRequest, SendRequest, SendResponse,
+};
+use crate::images::{lnd_network, LndNode};
+use crate::state::State;
+use anyhow::{anyhow, Result};
+use tonic::codegen::InterceptedService;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel as Transport, ClientTlsConfig, Endpoint};
+use tonic::{Request, Status};
+
+// adds the hex encoded macaroon to every call
+#[derive(Clone)]
+pub struct MacaroonInterceptor {
+    macaroon: MetadataValue<Ascii>,
+}
+
+impl Interceptor for MacaroonInterceptor {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        req.metadata_mut().insert("macaroon", self.macaroon.clone());
+        Ok(req)
+    }
+}
+
+// a channel that every generated lnd client can be built on
+pub type Authed = InterceptedService<Transport, MacaroonInterceptor>;
+
+// lnd's grpc port on the host, authenticated with admin.macaroon
+pub async fn connect(proj: &str, lnd: &LndNode, state: &State) -> Result<Authed> {
+    let dir = format!("vol/{}/{}", proj, lnd.name);
+    let cert = std::fs::read(format!("{}/tls.cert", dir))?;
+    let network = lnd_network(&lnd.network);
+    let mac_path = format!("{}/data/chain/bitcoin/{}/admin.macaroon", dir, network);
+    let macaroon = hex::encode(std::fs::read(mac_path)?).parse()?;
+    // lnd's self signed cert always has "localhost" in it
+    let tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(cert))
+        .domain_name("localhost");
+    let port = state.host_port(&lnd.name, &lnd.port);
+    let channel = Endpoint::from_shared(format!("https://localhost:{}", port))?
+        .tls_config(tls)?
+        .connect()
+        .await?;
+    Ok(InterceptedService::new(
+        channel,
+        MacaroonInterceptor { macaroon },
+    ))
+}
+
+// the main lnrpc.Lightning service
+pub struct LndClient {
+    client: LightningClient<Authed>,
+}
+
+impl LndClient {
+    pub async fn new(proj: &str, lnd: &LndNode, state: &State) -> Result<Self> {
+        let client = LightningClient::new(connect(proj, lnd, state).await?);
+        Ok(Self { client })
+    }
+    pub async fn get_info(&mut self) -> Result<GetInfoResponse> {
+        let res = self.client.get_info(GetInfoRequest {}).await?;
+        Ok(res.into_inner())
+    }
+}
+
+#[allow(dead_code)]
+impl LndClient {
+    pub async fn new_address(&mut self) -> Result<String> {
+        let req = NewAddressRequest {
+            r#type: AddressType::WitnessPubkeyHash as i32,
+            ..Default::default()
+        };
+        let res = self.client.new_address(req).await?;
+        Ok(res.into_inner().address)
+    }
+    // host is "{ip or domain}:{port}"
+    pub async fn connect_peer(&mut self, pubkey: &str, host: &str) -> Result<ConnectPeerResponse> {
+        let req = ConnectPeerRequest {
+            addr: Some(LightningAddress {
+                pubkey: pubkey.to_string(),
+                host: host.to_string(),
+            }),
+            ..Default::default()
+        };
+        let res = self.client.connect_peer(req).await?;
+        Ok(res.into_inner())
+    }
+    // waits for the funding tx to be published, not for it to confirm
+    pub async fn open_channel(
+        &mut self,
+        pubkey: &str,
+        amount: i64,
+        push_amount: i64,
+    ) -> Result<ChannelPoint> {
+        let req = OpenChannelRequest {
+            node_pubkey: hex::decode(pubkey)?,
+            local_funding_amount: amount,
+            push_sat: push_amount,
+            ..Default::default()
+        };
+        let res = self.client.open_channel_sync(req).await?;
+        Ok(res.into_inner())
+    }
+    pub async fn add_invoice(&mut self, value: i64, memo: &str) -> Result<AddInvoiceResponse> {
+        let req = Invoice {
+            value,
+            memo: memo.to_string(),
+            ..Default::default()
+        };
+        let res = self.client.add_invoice(req).await?;
+        Ok(res.into_inner())
+    }
+    pub async fn send_payment(&mut self, payment_request: &str) -> Result<SendResponse> {
+        let req = SendRequest {
+            payment_request: payment_request.to_string(),
+            ..Default::default()
+        };
+        let res = self.client.send_payment_sync(req).await?.into_inner();
+        if !res.payment_error.is_empty() {
+            return Err(anyhow!("payment failed: {}", res.payment_error));
+        }
+        Ok(res)
+    }
+    pub async fn list_channels(&mut self) -> Result<Vec<Channel>> {
+        let res = self
+            .client
+            .list_channels(ListChannelsRequest::default())
+            .await?;
+        Ok(res.into_inner().channels)
+    }
+}
@@ -1 +1,23 @@
+pub mod client;
+pub mod router;
+pub mod signer;
 pub mod unlocker;
+pub mod walletkit;
+
+// generated from the protos in proto/, only partly used
+#[allow(dead_code, clippy::all)]
+pub mod lnrpc {
+    tonic::include_proto!("lnrpc");
+}
+#[allow(dead_code, clippy::all)]
+pub mod routerrpc {
+    tonic::include_proto!("routerrpc");
+}
+#[allow(dead_code, clippy::all)]
+pub mod signrpc {
+    tonic::include_proto!("signrpc");
+}
+#[allow(dead_code, clippy::all)]
+pub mod walletrpc {
+    tonic::include_proto!("walletrpc");
+}
@@ -0,0 +1,66 @@
+use super::client::{connect, Authed};
+use super::lnrpc::payment::PaymentStatus;
+use super::lnrpc::Payment;
+use super::routerrpc::router_client::RouterClient;
+use super::routerrpc::{
+    HtlcEvent, SendPaymentRequest, SubscribeHtlcEventsRequest, TrackPaymentRequest,
+};
+use crate::images::LndNode;
+use crate::state::State;
+use anyhow::{anyhow, Result};
+use tonic::codec::Streaming;
+
+// routerrpc.Router, for payments that report their progress
+pub struct LndRouter {
+    client: RouterClient<Authed>,
+}
+
+#[allow(dead_code)]
+impl LndRouter {
+    pub async fn new(proj: &str, lnd: &LndNode, state: &State) -> Result<Self> {
+        let client = RouterClient::new(connect(proj, lnd, state).await?);
+        Ok(Self { client })
+    }
+    // pay an invoice and wait for the payment to succeed or fail
+    pub async fn send_payment_v2(
+        &mut self,
+        payment_request: &str,
+        timeout_seconds: i32,
+        fee_limit_sat: i64,
+    ) -> Result<Payment> {
+        let req = SendPaymentRequest {
+            payment_request: payment_request.to_string(),
+            timeout_seconds,
+            fee_limit_sat,
+            ..Default::default()
+        };
+        let stream = self.client.send_payment_v2(req).await?.into_inner();
+        final_payment(stream).await
+    }
+    // wait for an earlier payment to succeed or fail
+    pub async fn track_payment_v2(&mut self, payment_hash: &str) -> Result<Payment> {
+        let req = TrackPaymentRequest {
+            payment_hash: hex::decode(payment_hash)?,
+            no_inflight_updates: true,
+        };
+        let stream = self.client.track_payment_v2(req).await?.into_inner();
+        final_payment(stream).await
+    }
+    pub async fn subscribe_htlc_events(&mut self) -> Result<Streaming<HtlcEvent>> {
+        let req = SubscribeHtlcEventsRequest {};
+        Ok(self.client.subscribe_htlc_events(req).await?.into_inner())
+    }
+}
+
+async fn final_payment(mut stream: Streaming<Payment>) -> Result<Payment> {
+    while let Some(payment) = stream.message().await? {
+        match payment.status() {
+            PaymentStatus::Succeeded => return Ok(payment),
+            PaymentStatus::Failed => {
+                return Err(anyhow!("payment failed: {:?}", payment.failure_reason()))
+            }
+            _ => (),
+        }
+    }
+    Err(anyhow!("payment stream ended before the payment settled"))
+}
@@ -0,0 +1,37 @@
+use super::client::{connect, Authed};
+use super::signrpc::signer_client::SignerClient;
+use super::signrpc::{KeyLocator, SignMessageReq};
+use crate::images::LndNode;
+use crate::state::State;
+use anyhow::Result;
+
+// signrpc.Signer, for signing with the node's keys
+pub struct LndSigner {
+    client: SignerClient<Authed>,
+}
+
+#[allow(dead_code)]
+impl LndSigner {
+    pub async fn new(proj: &str, lnd: &LndNode, state: &State) -> Result<Self> {
+        let client = SignerClient::new(connect(proj, lnd, state).await?);
+        Ok(Self { client })
+    }
+    // ecdsa signature of sha256(msg) with the key at family/index
+    pub async fn sign_message(
+        &mut self,
+        msg: &[u8],
+        key_family: i32,
+        key_index: i32,
+    ) -> Result<Vec<u8>> {
+        let req = SignMessageReq {
+            msg: msg.to_vec(),
+            key_loc: Some(KeyLocator {
+                key_family,
+                key_index,
+            }),
+            ..Default::default()
+        };
+        let res = self.client.sign_message(req).await?;
+        Ok(res.into_inner().signature)
+    }
+}
@@ -1,5 +1,8 @@
-use anyhow::Result;
+use crate::config::ReadyConfig;
+use crate::ready::retry;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::time::Duration;
 
 pub struct LndUnlocker {
@@ -7,15 +10,71 @@ pub struct LndUnlocker {
     pub url: String,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum UnlockError {
+    #[error("wrong wallet password")]
+    WrongPassword,
+    #[error("bad seed: {0}")]
+    BadSeed(String),
+    #[error("lnd rest api not reachable: {0}")]
+    Unreachable(String),
+    #[error("lnd error: {0}")]
+    Lnd(String),
+}
+
+impl From<reqwest::Error> for UnlockError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() || e.is_timeout() {
+            UnlockError::Unreachable(e.to_string())
+        } else {
+            UnlockError::Lnd(e.to_string())
+        }
+    }
+}
+
+// lnrpc.WalletState
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WalletState {
+    // the zero value, left out of the json
+    #[default]
+    NonExisting,
+    Locked,
+    Unlocked,
+    RpcActive,
+    ServerActive,
+    WaitingToStart,
+}
+
+impl WalletState {
+    // the rpc server is up and takes macaroon authenticated calls
+    pub fn is_active(&self) -> bool {
+        matches!(self, WalletState::RpcActive | WalletState::ServerActive)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateResponse {
+    #[serde(default)]
+    state: WalletState,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct InitWalletRequest {
     cipher_seed_mnemonic: Vec<String>,
     wallet_password: String,
 }
+
 #[derive(Serialize, Deserialize, Debug)]
-pub struct InitWalletResponse {
-    admin_macaroon: Option<String>,
-    message: Option<String>,
+pub struct GenSeedResponse {
+    cipher_seed_mnemonic: Vec<String>,
+    enciphered_seed: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -30,51 +89,143 @@ impl LndUnlocker {
         let client = reqwest::Client::builder()
             .timeout(Duration::from_secs(10))
             .add_root_certificate(cert)
-            .danger_accept_invalid_certs(true)
-            .build()
-            .expect("couldnt build reqwest client");
+            .build()?;
         Ok(Self {
             url: format!("localhost:{}", port),
             client,
         })
     }
+    pub async fn state(&self) -> Result<WalletState, UnlockError> {
+        let route = format!("https://{}/v1/state", self.url);
+        let res = check(self.client.get(route.as_str()).send().await?).await?;
+        let st: StateResponse = res.json().await?;
+        Ok(st.state)
+    }
+    // wait for the rest api to come up and for lnd to leave WAITING_TO_START,
+    // other errors end the wait right away
+    pub async fn wait_for_state(&self, conf: &ReadyConfig) -> Result<WalletState, UnlockError> {
+        let res = retry("lnd wallet", conf, || async {
+            match self.state().await {
+                Ok(WalletState::WaitingToStart) => Err(anyhow!("waiting to start")),
+                Err(e @ UnlockError::Unreachable(_)) => Err(e.into()),
+                done => Ok(done),
+            }
+        })
+        .await;
+        res.unwrap_or_else(|e| Err(UnlockError::Unreachable(e.to_string())))
+    }
+    // init or unlock, depending on the wallet state
+    pub async fn ensure_unlocked(
+        &self,
+        password: &str,
+        mnemonic: &[String],
+        conf: &ReadyConfig,
+    ) -> Result<WalletState, UnlockError> {
+        let st = self.wait_for_state(conf).await?;
+        match st {
+            WalletState::NonExisting => {
+                if mnemonic.is_empty() {
+                    return Err(UnlockError::BadSeed("no seed to init the wallet".into()));
+                }
+                self.init_wallet(password, mnemonic.to_vec()).await?;
+            }
+            WalletState::Locked => self.unlock_wallet(password).await?,
+            _ => (),
+        }
+        Ok(st)
+    }
     pub async fn init_wallet(
         &self,
         password: &str,
         cipher_seed_mnemonic: Vec<String>,
-    ) -> Result<InitWalletResponse> {
+    ) -> Result<(), UnlockError> {
         let wallet_password = base64::encode(password.as_bytes());
         let body = InitWalletRequest {
             cipher_seed_mnemonic,
             wallet_password,
         };
         let route = format!("https://{}/v1/initwallet", self.url);
-        match self
-            .client
-            .post(route.as_str())
-            .json(&body)
-            .header("Content-Type", "application/json")
-            .send()
-            .await
-        {
-            Ok(res) => Ok(res.json().await?),
-            Err(e) => Err(anyhow::anyhow!("INIT ERR {:?}", e)),
-        }
+        let res = self.client.post(route.as_str()).json(&body).send().await?;
+        check(res).await?;
+        Ok(())
+    }
+    // a fresh aezeed, without a seed passphrase
+    pub async fn gen_seed(&self) -> Result<Vec<String>, UnlockError> {
+        let route = format!("https://{}/v1/genseed", self.url);
+        let res = check(self.client.get(route.as_str()).send().await?).await?;
+        let seed: GenSeedResponse = res.json().await?;
+        Ok(seed.cipher_seed_mnemonic)
     }
-    pub async fn unlock_wallet(&self, password: &str) -> Result<()> {
+    // only served while the wallet is locked, unlocks it with the new password
+    pub async fn change_password(&self, current: &str, new: &str) -> Result<(), UnlockError> {
+        let body = ChangePasswordRequest {
+            current_password: base64::encode(current.as_bytes()),
+            new_password: base64::encode(new.as_bytes()),
+        };
+        let route = format!("https://{}/v1/changepassword", self.url);
+        check(self.client.post(route.as_str()).json(&body).send().await?).await?;
+        Ok(())
+    }
+    pub async fn unlock_wallet(&self, password: &str) -> Result<(), UnlockError> {
         let wallet_password = base64::encode(password.as_bytes());
         let body = UnlockWalletRequest { wallet_password };
         let route = format!("https://{}/v1/unlockwallet", self.url);
-        match self
-            .client
-            .post(route.as_str())
-            .json(&body)
-            .header("Content-Type", "application/json")
-            .send()
-            .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => Err(anyhow::anyhow!("UNLOCK ERR {:?}", e)),
-        }
+        check(self.client.post(route.as_str()).json(&body).send().await?).await?;
+        Ok(())
+    }
+}
+
+// turn an error status into an UnlockError
+async fn check(res: reqwest::Response) -> Result<reqwest::Response, UnlockError> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status();
+    let body: Value = res.json().await.unwrap_or_default();
+    // grpc-gateway puts the error in "message", older lnds in "error"
+    let msg = body["message"]
+        .as_str()
+        .or(body["error"].as_str())
+        .map(|m| m.to_string())
+        .unwrap_or(status.to_string());
+    Err(classify(&msg))
+}
+
+fn classify(msg: &str) -> UnlockError {
+    let m = msg.to_lowercase();
+    if m.contains("invalid passphrase") || m.contains("invalid password") {
+        UnlockError::WrongPassword
+    } else if ["mnemonic", "checksum", "invalid seed", "aezeed"]
+        .iter()
+        .any(|w| m.contains(w))
+    {
+        UnlockError::BadSeed(msg.to_string())
+    } else {
+        UnlockError::Lnd(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let e = classify("invalid passphrase for master public key");
+        assert!(matches!(e, UnlockError::WrongPassword));
+        let e = classify("checksum mismatch");
+        assert!(matches!(e, UnlockError::BadSeed(_)));
+        let e = classify("password must have at least 8 characters");
+        assert!(matches!(e, UnlockError::Lnd(_)));
+        let e = classify("wallet already exists");
+        assert!(matches!(e, UnlockError::Lnd(_)));
+    }
+
+    #[test]
+    fn test_wallet_state() {
+        let st: StateResponse = serde_json::from_str(r#"{"state":"RPC_ACTIVE"}"#).unwrap();
+        assert!(st.state.is_active());
+        let st: StateResponse = serde_json::from_str("{}").unwrap();
+        assert_eq!(st.state, WalletState::NonExisting);
     }
 }
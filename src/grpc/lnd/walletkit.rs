@@ -0,0 +1,55 @@
+use super::client::{connect, Authed};
+use super::lnrpc::Utxo;
+use super::walletrpc::fund_psbt_request::{Fees, Template};
+use super::walletrpc::wallet_kit_client::WalletKitClient;
+use super::walletrpc::{
+    AddrRequest, FundPsbtRequest, FundPsbtResponse, ListUnspentRequest, TxTemplate,
+};
+use crate::images::LndNode;
+use crate::state::State;
+use anyhow::Result;
+use std::collections::HashMap;
+
+// walletrpc.WalletKit, for on-chain funds
+pub struct LndWalletKit {
+    client: WalletKitClient<Authed>,
+}
+
+#[allow(dead_code)]
+impl LndWalletKit {
+    pub async fn new(proj: &str, lnd: &LndNode, state: &State) -> Result<Self> {
+        let client = WalletKitClient::new(connect(proj, lnd, state).await?);
+        Ok(Self { client })
+    }
+    // next unused address of the default account
+    pub async fn next_addr(&mut self) -> Result<String> {
+        let res = self.client.next_addr(AddrRequest::default()).await?;
+        Ok(res.into_inner().addr)
+    }
+    pub async fn list_unspent(&mut self, min_confs: i32, max_confs: i32) -> Result<Vec<Utxo>> {
+        let req = ListUnspentRequest {
+            min_confs,
+            max_confs,
+            ..Default::default()
+        };
+        let res = self.client.list_unspent(req).await?;
+        Ok(res.into_inner().utxos)
+    }
+    // a funded psbt paying {address: sats}, with the picked inputs locked
+    pub async fn fund_psbt(
+        &mut self,
+        outputs: HashMap<String, u64>,
+        sat_per_vbyte: u64,
+    ) -> Result<FundPsbtResponse> {
+        let req = FundPsbtRequest {
+            template: Some(Template::Raw(TxTemplate {
+                inputs: vec![],
+                outputs,
+            })),
+            fees: Some(Fees::SatPerVbyte(sat_per_vbyte)),
+            ..Default::default()
+        };
+        let res = self.client.fund_psbt(req).await?;
+        Ok(res.into_inner())
+    }
+}
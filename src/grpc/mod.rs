@@ -1 +1,2 @@
+pub mod cln;
 pub mod lnd;
@@ -1,20 +1,79 @@
 use crate::config;
-use crate::utils::{default_volumes, expose, exposed_ports, files_volume, host_config};
+use crate::external::{container_host, Endpoint};
+use crate::postgres::PostgresConn;
+use crate::utils::{
+    container_name, default_volumes, expose, exposed_ports, files_volume, host_config, labels,
+    net_config, subnet,
+};
+use anyhow::Result;
 use bollard::container::Config;
+use bollard_stubs::models::HostConfig;
 
 // ports are tcp
 // volumes are mapped to {PWD}/vol/{name}:
 
+// a handful per swarm, so the size of a variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 pub enum Node {
     Btc(BtcNode),
     Lnd(LndNode),
+    Proxy(ProxyNode),
     Relay(RelayNode),
+    Cln(ClnNode),
+    Postgres(PostgresNode),
+    Tribes(TribesNode),
+    Broker(BrokerNode),
+    Meme(MemeNode),
+}
+impl Node {
+    pub fn name(&self) -> String {
+        match self {
+            Node::Btc(n) => n.name.clone(),
+            Node::Lnd(n) => n.name.clone(),
+            Node::Proxy(n) => n.name.clone(),
+            Node::Relay(n) => n.name.clone(),
+            Node::Cln(n) => n.name.clone(),
+            Node::Postgres(n) => n.name.clone(),
+            Node::Tribes(n) => n.name.clone(),
+            Node::Broker(n) => n.name.clone(),
+            Node::Meme(n) => n.name.clone(),
+        }
+    }
+    // provided from outside, there is no container to manage
+    pub fn is_external(&self) -> bool {
+        matches!(self, Node::Btc(b) if b.external.is_some())
+    }
+    // names of the nodes that must be up before this one.
+    // the "postgres" field of relay, tribes and meme names the swarm's postgres node
+    // when their database lives there, and is unset for Config::postgres or sqlite
+    pub fn deps(&self) -> Vec<String> {
+        match self {
+            Node::Btc(_) => vec![],
+            Node::Lnd(n) => vec![n.btc.clone()],
+            Node::Proxy(n) => vec![n.lnd.clone()],
+            Node::Relay(n) => {
+                let mut deps = vec![n.proxy.clone()];
+                deps.extend(n.postgres.clone());
+                deps.extend(n.services.clone());
+                deps
+            }
+            Node::Cln(n) => vec![n.btc.clone()],
+            Node::Postgres(_) => vec![],
+            Node::Tribes(n) => n.postgres.iter().cloned().collect(),
+            Node::Broker(n) => vec![n.tribes.clone()],
+            Node::Meme(n) => n.postgres.iter().cloned().collect(),
+        }
+    }
 }
 pub struct BtcNode {
     pub name: String,
     pub network: String,
     pub user: String,
     pub pass: String,
+    // Config::bitcoind, no container is started for it
+    pub external: Option<Endpoint>,
+    // rpc published on every interface, see Exposure::Public
+    pub public: bool,
 }
 impl BtcNode {
     pub fn new(name: &str, network: &str, user: &str, pass: &str) -> Self {
@@ -23,6 +82,35 @@ impl BtcNode {
             network: network.to_string(),
             user: user.to_string(),
             pass: pass.to_string(),
+            external: None,
+            public: false,
+        }
+    }
+    // the rpc credentials come from the url
+    pub fn external(name: &str, network: &str, url: &str) -> Result<Self> {
+        let ep = Endpoint::parse(url, BTC_RPC_PORT.parse().ok())?;
+        let mut node = Self::new(name, network, &ep.user, &ep.pass);
+        node.external = Some(ep);
+        Ok(node)
+    }
+    // rpc and zmq host, as seen from other containers
+    pub fn host(&self) -> String {
+        match &self.external {
+            Some(ep) => container_host(&ep.host),
+            None => format!("{}.sphinx", self.name),
+        }
+    }
+    // lnd assumes the default rpc port when there is none
+    pub fn rpc_host(&self) -> String {
+        match &self.external {
+            Some(_) => format!("{}:{}", self.host(), self.rpc_port()),
+            None => self.host(),
+        }
+    }
+    pub fn rpc_port(&self) -> String {
+        match &self.external {
+            Some(ep) => ep.port.to_string(),
+            None => BTC_RPC_PORT.to_string(),
         }
     }
 }
@@ -30,27 +118,128 @@ pub struct LndNode {
     pub name: String,
     pub network: String,
     pub port: String,
-    pub dir: String,
+    pub http_port: Option<String>,
+    pub btc: String,
 }
 impl LndNode {
-    pub fn new(name: &str, network: &str, port: &str, dir: &str) -> Self {
+    pub fn new(name: &str, network: &str, port: &str, http_port: Option<&str>, btc: &str) -> Self {
         Self {
             name: name.to_string(),
             network: network.to_string(),
             port: port.to_string(),
-            dir: dir.to_string(),
+            http_port: http_port.map(|p| p.to_string()),
+            btc: btc.to_string(),
         }
     }
 }
 pub struct RelayNode {
     pub name: String,
     pub port: String,
+    pub proxy: String,
+    // sqlite when unset
+    pub db: Option<PostgresConn>,
+    pub postgres: Option<String>,
+    // relay's own defaults when unset
+    pub tribes: Option<Endpoint>,
+    // the broker listens on the tribes host
+    pub mqtt_port: Option<String>,
+    pub meme: Option<Endpoint>,
+    // swarm nodes behind the tribes, broker and meme endpoints
+    pub services: Vec<String>,
 }
 impl RelayNode {
-    pub fn new(name: &str, port: &str) -> Self {
+    pub fn new(name: &str, port: &str, proxy: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            port: port.to_string(),
+            proxy: proxy.to_string(),
+            db: None,
+            postgres: None,
+            tribes: None,
+            mqtt_port: None,
+            meme: None,
+            services: vec![],
+        }
+    }
+}
+pub struct PostgresNode {
+    pub name: String,
+    // of the "postgres" superuser
+    pub pass: String,
+}
+impl PostgresNode {
+    pub fn new(name: &str, pass: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            pass: pass.to_string(),
+        }
+    }
+}
+pub struct TribesNode {
+    pub name: String,
+    pub port: String,
+    pub db: PostgresConn,
+    pub postgres: Option<String>,
+    // published here for the broker, which shares this container's network
+    pub broker_port: Option<String>,
+}
+impl TribesNode {
+    pub fn new(name: &str, port: &str, db: PostgresConn) -> Self {
+        Self {
+            name: name.to_string(),
+            port: port.to_string(),
+            db,
+            postgres: None,
+            broker_port: None,
+        }
+    }
+}
+// media server for relay attachments
+pub struct MemeNode {
+    pub name: String,
+    pub port: String,
+    pub db: PostgresConn,
+    pub postgres: Option<String>,
+}
+impl MemeNode {
+    pub fn new(name: &str, port: &str, db: PostgresConn) -> Self {
         Self {
             name: name.to_string(),
             port: port.to_string(),
+            db,
+            postgres: None,
+        }
+    }
+}
+// mqtt broker that relays of a tribe talk through,
+// relay looks for it on the tribes host so it runs in the tribes network namespace
+pub struct BrokerNode {
+    pub name: String,
+    pub port: String,
+    pub tribes: String,
+}
+impl BrokerNode {
+    pub fn new(name: &str, port: &str, tribes: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            port: port.to_string(),
+            tribes: tribes.to_string(),
+        }
+    }
+}
+pub struct ClnNode {
+    pub name: String,
+    pub network: String,
+    pub idx: u16,
+    pub btc: String,
+}
+impl ClnNode {
+    pub fn new(name: &str, network: &str, idx: u16, btc: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            network: network.to_string(),
+            idx,
+            btc: btc.to_string(),
         }
     }
 }
@@ -61,6 +250,7 @@ pub struct ProxyNode {
     pub admin_port: String,
     pub admin_token: String,
     pub store_key: String,
+    pub lnd: String,
 }
 impl ProxyNode {
     pub fn new(
@@ -70,6 +260,7 @@ impl ProxyNode {
         admin_port: &str,
         admin_token: &str,
         store_key: &str,
+        lnd: &str,
     ) -> Self {
         Self {
             name: name.to_string(),
@@ -78,23 +269,28 @@ impl ProxyNode {
             admin_port: admin_port.to_string(),
             admin_token: admin_token.to_string(),
             store_key: store_key.to_string(),
+            lnd: lnd.to_string(),
         }
     }
 }
 
-pub fn lnd(project: &str, lnd: &LndNode, btc: &BtcNode, http_port: Option<&str>) -> Config<String> {
-    let network = match lnd.network.as_str() {
+// lnd's name for the swarm network, used in its flags and
+// its chain dir, e.g. data/chain/bitcoin/mainnet/admin.macaroon
+pub fn lnd_network(network: &str) -> &'static str {
+    match network {
         "bitcoin" => "mainnet",
         "simnet" => "simnet",
         "regtest" => "regtest",
         _ => "regtest",
-    };
+    }
+}
+
+pub fn lnd(project: &str, lnd: &LndNode, btc: &BtcNode) -> Config<String> {
+    let network = lnd_network(&lnd.network);
     let version = "v0.14.3-beta.rc1".to_string();
     let peering_port = "9735";
     let mut ports = vec![peering_port, lnd.port.as_str()];
     let vols = vec!["/root/.lnd"];
-    let btc_link = format!("{}.sphinx", &btc.name);
-    let links = Some(vec![btc_link.as_str()]);
     let mut cmd = vec![
         format!("--bitcoin.{}", network).to_string(),
         format!("--rpclisten=0.0.0.0:{}", &lnd.port).to_string(),
@@ -102,16 +298,16 @@ pub fn lnd(project: &str, lnd: &LndNode, btc: &BtcNode, http_port: Option<&str>)
         format!("--alias={}", &lnd.name).to_string(),
         format!("--bitcoind.rpcuser={}", &btc.user).to_string(),
         format!("--bitcoind.rpcpass={}", &btc.pass).to_string(),
-        format!("--bitcoind.rpchost={}.sphinx", &btc.name).to_string(),
-        format!("--bitcoind.zmqpubrawblock=tcp://{}.sphinx:28332", &btc.name).to_string(),
-        format!("--bitcoind.zmqpubrawtx=tcp://{}.sphinx:28333", &btc.name).to_string(),
+        format!("--bitcoind.rpchost={}", btc.rpc_host()),
+        format!("--bitcoind.zmqpubrawblock=tcp://{}:28332", btc.host()),
+        format!("--bitcoind.zmqpubrawtx=tcp://{}:28333", btc.host()),
         "--debuglevel=info".to_string(),
         "--accept-keysend".to_string(),
         "--bitcoin.active".to_string(),
         "--bitcoin.node=bitcoind".to_string(),
         "--bitcoin.defaultchanconfs=2".to_string(),
     ];
-    if let Some(hp) = http_port {
+    if let Some(hp) = &lnd.http_port {
         ports.push(hp);
         let rest_host = "0.0.0.0";
         cmd.push(format!("--restlisten={}:{}", rest_host, hp).to_string());
@@ -119,19 +315,100 @@ pub fn lnd(project: &str, lnd: &LndNode, btc: &BtcNode, http_port: Option<&str>)
     Config {
         image: Some(format!("lightninglabs/lnd:{}", version).to_string()),
         hostname: Some(format!("{}.sphinx", &lnd.name)),
+        labels: labels(project, "lnd", &lnd.name),
         exposed_ports: exposed_ports(ports.clone()),
-        host_config: host_config(project, &lnd.name, ports, vols, None, links),
+        host_config: host_config(project, &lnd.name, ports, vols, None),
+        networking_config: net_config(project, &lnd.name),
         cmd: Some(cmd),
         ..Default::default()
     }
 }
 
-pub fn postgres(project: &str) -> Config<String> {
-    let name = "postgres";
+pub const POSTGRES_PORT: &str = "5432";
+
+pub fn postgres(project: &str, pg: &PostgresNode) -> Config<String> {
+    let version = "14";
+    let name = pg.name.as_str();
+    let ports = vec![POSTGRES_PORT];
+    let vols = vec!["/var/lib/postgresql/data"];
+    Config {
+        image: Some(format!("postgres:{}", version)),
+        hostname: Some(format!("{}.sphinx", name)),
+        labels: labels(project, "postgres", name),
+        exposed_ports: exposed_ports(ports.clone()),
+        host_config: host_config(project, name, ports, vols, None),
+        networking_config: net_config(project, name),
+        env: Some(vec![format!("POSTGRES_PASSWORD={}", pg.pass)]),
+        ..Default::default()
+    }
+}
+
+pub fn tribes(project: &str, tribes: &TribesNode) -> Config<String> {
+    let version = "0.1.0";
+    let name = tribes.name.as_str();
+    let mut ports = vec![tribes.port.as_str()];
+    ports.extend(tribes.broker_port.as_deref());
+    Config {
+        image: Some(format!("sphinxlightning/sphinx-tribes:{}", version)),
+        hostname: Some(format!("{}.sphinx", name)),
+        labels: labels(project, "tribes", name),
+        exposed_ports: exposed_ports(ports.clone()),
+        host_config: host_config(project, name, ports, vec![], None),
+        networking_config: net_config(project, name),
+        env: Some(vec![
+            format!("PORT={}", tribes.port),
+            format!("DATABASE_URL={}", tribes.db.url()),
+        ]),
+        ..Default::default()
+    }
+}
+
+// anonymous, the swarm network is the only thing in front of it.
+// its ports are published by the tribes container, whose network it joins
+pub fn broker(project: &str, broker: &BrokerNode) -> Config<String> {
+    let version = "2.0.15";
+    let name = broker.name.as_str();
+    let host_config = HostConfig {
+        binds: Some(default_volumes(project, name, vec!["/mosquitto/data"])),
+        network_mode: Some(format!(
+            "container:{}",
+            container_name(project, &broker.tribes)
+        )),
+        ..Default::default()
+    };
+    Config {
+        image: Some(format!("eclipse-mosquitto:{}", version)),
+        labels: labels(project, "broker", name),
+        host_config: Some(host_config),
+        cmd: Some(vec![
+            "mosquitto".to_string(),
+            "-c".to_string(),
+            "/mosquitto-no-auth.conf".to_string(),
+        ]),
+        ..Default::default()
+    }
+}
+
+// uploads are kept on disk in the meme volume, not in s3
+pub fn meme(project: &str, meme: &MemeNode) -> Config<String> {
+    let version = "0.1.0";
+    let name = meme.name.as_str();
+    let ports = vec![meme.port.as_str()];
     Config {
-        image: Some("postgres".to_string()),
+        image: Some(format!("sphinxlightning/sphinx-meme:{}", version)),
         hostname: Some(format!("{}.sphinx", name)),
-        host_config: host_config(project, name, vec![], vec![], None, None),
+        labels: labels(project, "meme", name),
+        exposed_ports: exposed_ports(ports.clone()),
+        host_config: host_config(project, name, ports, vec!["/meme"], None),
+        networking_config: net_config(project, name),
+        env: Some(vec![
+            format!("PORT={}", meme.port),
+            format!("HOST={}.sphinx:{}", name, meme.port),
+            format!("DATABASE_URL={}", meme.db.url()),
+            // sphinx-meme's local storage, instead of its default s3 bucket
+            "STORAGE_MODE=local".to_string(),
+            "LOCAL_DIR=/meme".to_string(),
+        ]),
         ..Default::default()
     }
 }
@@ -141,19 +418,42 @@ pub fn relay(
     relay: &RelayNode,
     lnd: &LndNode,
     proxy: &ProxyNode,
-    proxy_admin_token: &str,
+    host_port: &str,
 ) -> Config<String> {
     let relay_version = "v2.2.10".to_string();
-    let vols = vec!["/creds"];
-    let mut conf = config::RelayConfig::new(&relay.name, &relay.port);
+    let vols = vec!["/relay"];
+    // lnd and proxy certs and macaroons, see RelayConfig::lnd and ::proxy
+    let mut extra_vols = default_volumes(project, &lnd.name, vec!["/lnd"]);
+    extra_vols.extend(default_volumes(project, &proxy.name, vec!["/proxy"]));
+    let mut conf = config::RelayConfig::new(&relay.port, host_port);
     conf.lnd(lnd);
-    conf.proxy(&proxy, proxy_admin_token);
-    let img = "sphinx-relay";
-    // let img = "sphinxlightning/sphinx-relay";
+    conf.proxy(proxy);
+    if let Some(db) = &relay.db {
+        conf.postgres(db);
+    }
+    if let Some(tribes) = &relay.tribes {
+        conf.tribes(tribes);
+    }
+    if let Some(port) = &relay.mqtt_port {
+        conf.tribes_mqtt_port = port.clone();
+    }
+    if let Some(meme) = &relay.meme {
+        conf.meme(meme);
+    }
+    // let img = "sphinx-relay";
+    let img = "sphinxlightning/sphinx-relay";
     Config {
         image: Some(format!("{}:{}", img, relay_version)),
         hostname: Some(format!("{}.sphinx", &relay.name)),
-        host_config: host_config(project, &relay.name, vec![&relay.port], vols, None, None),
+        labels: labels(project, "relay", &relay.name),
+        host_config: host_config(
+            project,
+            &relay.name,
+            vec![&relay.port],
+            vols,
+            Some(extra_vols),
+        ),
+        networking_config: net_config(project, &relay.name),
         env: Some(config::relay_env_config(&conf)),
         ..Default::default()
     }
@@ -164,38 +464,38 @@ pub fn proxy(project: &str, proxy: &ProxyNode, lnd: &LndNode) -> Config<String>
     let version = "0.1.2".to_string();
     // let img = "sphinx-proxy";
     // let version = "latest";
+    let network = lnd_network(&proxy.network);
     let macpath = format!(
         "--macaroon-location=/lnd/data/chain/bitcoin/{}/admin.macaroon",
-        proxy.network
+        network
     );
-    let lnd_host = format!("{}.sphinx", lnd.name);
-    let links = vec![lnd_host.as_str()];
     let vols = vec!["/cert", "/badger", "/macaroons"];
     let mut extra_vols = default_volumes(project, &lnd.name, vec!["/lnd"]);
     extra_vols.push(files_volume());
     Config {
         image: Some(format!("{}:{}", img, version)),
         hostname: Some(format!("{}.sphinx", proxy.name)),
+        labels: labels(project, "proxy", &proxy.name),
         host_config: host_config(
             project,
             &proxy.name,
             vec![&proxy.port, &proxy.admin_port],
             vols,
             Some(extra_vols),
-            Some(links),
         ),
+        networking_config: net_config(project, &proxy.name),
         cmd: Some(vec![
             "/app/sphinx-proxy".to_string(),
             "--configfile=/files/lnd_proxy.conf".to_string(),
             macpath.to_string(),
             "--bitcoin.active".to_string(),
             "--bitcoin.basefee=0".to_string(),
-            format!("--bitcoin.{}", &proxy.network),
+            format!("--bitcoin.{}", network),
             format!("--rpclisten=0.0.0.0:{}", &proxy.port),
             format!("--store-key={}", &proxy.store_key),
             format!("--admin-token={}", &proxy.admin_token),
             format!("--admin-port={}", &proxy.admin_port),
-            format!("--lnd-ip={}", &lnd.name),
+            format!("--lnd-ip={}.sphinx", &lnd.name),
             format!("--lnd-port={}", &lnd.port),
             format!("--tlsextradomain={}.sphinx", proxy.name),
             "--tlscertpath=/cert/tls.cert".to_string(),
@@ -210,40 +510,49 @@ pub fn proxy(project: &str, proxy: &ProxyNode, lnd: &LndNode) -> Config<String>
     }
 }
 
+pub const BTC_RPC_PORT: &str = "18443";
+
 pub fn btc(project: &str, node: &BtcNode) -> Config<String> {
     let btc_version = "23.0";
-    let ports = vec!["18443", "28332", "28333"];
+    let ports = vec![BTC_RPC_PORT, "28332", "28333"];
     let vols = vec!["/home/bitcoin/.bitcoin"];
+    let mut cmd = vec![
+        format!("-{}=1", node.network),
+        format!("-rpcuser={}", node.user),
+        format!("-rpcpassword={}", node.pass),
+        format!("-rpcbind={}.sphinx", node.name),
+        // localhost ports are forwarded from the network gateway
+        format!("-rpcallowip={}", subnet(project).0),
+        "-rpcbind=0.0.0.0".to_string(),
+        "-rpcport=18443".to_string(),
+        "-server".to_string(),
+        "-fallbackfee=0.0002".to_string(),
+        "-zmqpubhashblock=tcp://0.0.0.0:28332".to_string(),
+        "-zmqpubhashtx=tcp://0.0.0.0:28333".to_string(),
+        "-rpcbind=127.0.0.1".to_string(),
+    ];
+    // remote clients keep their own address through iptables dnat
+    if node.public {
+        cmd.push("-rpcallowip=0.0.0.0/0".to_string());
+    }
     Config {
         image: Some(format!("ruimarinho/bitcoin-core:{}", btc_version)),
         hostname: Some(format!("{}.sphinx", &node.name)),
-        cmd: Some(vec![
-            format!("-{}=1", node.network),
-            format!("-rpcuser={}", node.user),
-            format!("-rpcpassword={}", node.pass),
-            format!("-rpcbind={}.sphinx", node.name),
-            "-rpcallowip=0.0.0.0/0".to_string(),
-            "-rpcbind=0.0.0.0".to_string(),
-            "-rpcport=18443".to_string(),
-            "-server".to_string(),
-            "-rpcallowip=0.0.0.0/0".to_string(),
-            "-fallbackfee=0.0002".to_string(),
-            "-zmqpubhashblock=tcp://0.0.0.0:28332".to_string(),
-            "-zmqpubhashtx=tcp://0.0.0.0:28333".to_string(),
-            "-rpcbind=127.0.0.1".to_string(),
-        ]),
-        host_config: host_config(project, &node.name, ports, vols, None, None),
+        labels: labels(project, "bitcoind", &node.name),
+        cmd: Some(cmd),
+        host_config: host_config(project, &node.name, ports, vols, None),
+        networking_config: net_config(project, &node.name),
         ..Default::default()
     }
 }
 
-struct Ports {
+pub struct Ports {
     pub main: String,
     pub grpc: String,
     pub mqtt: String,
     pub http: String,
 }
-fn vls_ports(idx: u16) -> Ports {
+pub fn vls_ports(idx: u16) -> Ports {
     let main_port = 9735 + idx;
     let grpc_port = 10019 + idx;
     let mqtt_port = 1883 + idx;
@@ -273,21 +582,20 @@ pub fn cln_vls(
         ps.http.as_str(),
     ];
     let vols = vec!["/root/.lightning"];
-    let btc_link = format!("{}.sphinx", &btc.name);
-    let links = Some(vec![btc_link.as_str()]);
     Config {
         image: Some(format!("sphinxlightning/sphinx-cln-vls:{}", version)),
         hostname: Some(format!("{}.sphinx", name)),
+        labels: labels(project, "cln", name),
         domainname: Some(name.to_string()),
         cmd: Some(vec![
             format!("--alias=sphinx-{}", name),
             format!("--addr=0.0.0.0:{}", ps.main),
             format!("--grpc-port={}", ps.grpc),
             "--network=regtest".to_string(),
-            "--bitcoin-rpcconnect=bitcoind".to_string(),
-            "--bitcoin-rpcport=18443".to_string(),
-            "--bitcoin-rpcuser=foo".to_string(),
-            "--bitcoin-rpcpassword=bar".to_string(),
+            format!("--bitcoin-rpcconnect={}", btc.host()),
+            format!("--bitcoin-rpcport={}", btc.rpc_port()),
+            format!("--bitcoin-rpcuser={}", btc.user),
+            format!("--bitcoin-rpcpassword={}", btc.pass),
             "--log-level=debug".to_string(),
             "--accept-htlc-tlv-types=133773310".to_string(),
             "--subdaemon=hsmd:/usr/local/libexec/c-lightning/sphinx-key-broker".to_string(),
@@ -301,7 +609,8 @@ pub fn cln_vls(
             format!("BROKER_MQTT_PORT={}", ps.mqtt),
             format!("BROKER_HTTP_PORT={}", ps.http),
         ]),
-        host_config: host_config(project, name, ports, vols, None, links),
+        host_config: host_config(project, name, ports, vols, None),
+        networking_config: net_config(project, name),
         ..Default::default()
     }
 }
@@ -1,15 +1,26 @@
 pub mod dock;
 
+mod cli;
 mod cmd;
 mod config;
 mod env;
+mod external;
 mod grpc;
 mod images;
 mod logs;
+mod postgres;
+mod ready;
 mod rocket_utils;
 mod routes;
+mod state;
+mod status;
+mod swarm;
 mod utils;
 
+use anyhow::Result;
+use clap::Parser;
+use cli::Command;
+
 #[rocket::main]
 async fn main() {
     simple_logger::SimpleLogger::new()
@@ -26,17 +37,24 @@ async fn main() {
         .with_module_level("_", log::LevelFilter::Error)
         .init()
         .unwrap();
-    let cmd = std::env::args().nth(1).expect("no cmd given");
+    let cli = cli::Cli::parse();
+    if let Err(e) = run(cli).await {
+        log::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
 
-    let d = dock::er();
-    match match cmd.as_str() {
-        "demo" => cmd::demo::run(d).await,
-        "down" => cmd::down::run(d).await,
-        "test" => cmd::test::run(d).await,
-        "stack" => cmd::stack::run(d).await,
-        _ => panic!("invalid cmd"),
-    } {
-        Ok(_) => (),
-        Err(e) => panic!("{:?}", e),
-    };
+async fn run(cli: cli::Cli) -> Result<()> {
+    let d = dock::er()?;
+    let project = cli.project();
+    match &cli.command {
+        Command::Up => cmd::stack::run(d, &project, cli.load_config()?, cli.port).await,
+        Command::Down { dry_run } => cmd::down::run(d, &project, *dry_run).await,
+        Command::Status => cmd::status::run(d, &project, cli.load_config()?).await,
+        Command::Logs { node, follow } => cmd::logs::run(d, &project, node, *follow).await,
+        Command::Exec { node, cmd } => cmd::exec::run(d, &project, node, cmd).await,
+        Command::Rotate { node } => cmd::rotate::run(d, &project, cli.load_config()?, node).await,
+        Command::Demo => cmd::demo::run(d, &project, cli.port).await,
+        Command::Test => cmd::test::run(d, &project).await,
+    }
 }
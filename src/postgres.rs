@@ -0,0 +1,133 @@
+use crate::external::container_host;
+use crate::images::{PostgresNode, POSTGRES_PORT};
+use crate::state::State;
+use anyhow::{anyhow, Result};
+use rocket::tokio;
+use tokio_postgres::{Client, NoTls};
+
+// how a node reaches its own database
+#[derive(Clone, Debug)]
+pub struct PostgresConn {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub pass: String,
+    pub database: String,
+}
+
+impl PostgresConn {
+    // a database and user named after the node, on the server at "url"
+    pub fn on(url: &str, name: &str, pass: &str) -> Result<Self> {
+        let (host, port) = host_port(url)?;
+        Ok(Self {
+            host: container_host(&host),
+            port,
+            user: name.to_string(),
+            pass: pass.to_string(),
+            database: name.to_string(),
+        })
+    }
+    // for clients that take a single url
+    pub fn url(&self) -> String {
+        format!(
+            "postgres://{}:{}@{}:{}/{}?sslmode=disable",
+            self.user, self.pass, self.host, self.port, self.database
+        )
+    }
+}
+
+// the superuser connection of the swarm's own postgres, over its published port
+pub fn admin_url(pg: &PostgresNode, state: &State) -> String {
+    format!(
+        "host=127.0.0.1 port={} user=postgres password={} dbname=postgres",
+        state.host_port(&pg.name, POSTGRES_PORT),
+        pg.pass
+    )
+}
+
+// the in-network address of the swarm's own postgres
+pub fn internal_url(pg: &PostgresNode) -> String {
+    format!("postgres://postgres@{}.sphinx:{}", pg.name, POSTGRES_PORT)
+}
+
+// host and port of a postgres url or key=value string
+pub fn host_port(url: &str) -> Result<(String, u16)> {
+    let c: tokio_postgres::Config = url.parse()?;
+    let host = match c.get_hosts().first() {
+        Some(tokio_postgres::config::Host::Tcp(h)) => h.clone(),
+        _ => return Err(anyhow!("no tcp host in postgres url")),
+    };
+    let port = c.get_ports().first().cloned().unwrap_or(5432);
+    Ok((host, port))
+}
+
+pub async fn connect(url: &str) -> Result<Client> {
+    let (client, conn) = tokio_postgres::connect(url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            log::warn!("postgres connection error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+pub async fn ping(url: &str) -> Result<()> {
+    connect(url).await?.simple_query("SELECT 1").await?;
+    Ok(())
+}
+
+// create the user and database of a relay, or reset the password of an existing user
+pub async fn ensure_db(admin_url: &str, db: &PostgresConn) -> Result<()> {
+    let client = connect(admin_url).await?;
+    let role = client
+        .query_opt("SELECT 1 FROM pg_roles WHERE rolname = $1", &[&db.user])
+        .await?;
+    let verb = if role.is_some() { "ALTER" } else { "CREATE" };
+    let stmt = format!(
+        "{} ROLE {} WITH LOGIN PASSWORD {}",
+        verb,
+        ident(&db.user),
+        literal(&db.pass)
+    );
+    client.batch_execute(&stmt).await?;
+    let exists = client
+        .query_opt(
+            "SELECT 1 FROM pg_database WHERE datname = $1",
+            &[&db.database],
+        )
+        .await?;
+    if exists.is_none() {
+        log::info!("=> creating postgres database {}", db.database);
+        let stmt = format!(
+            "CREATE DATABASE {} OWNER {}",
+            ident(&db.database),
+            ident(&db.user)
+        );
+        client.batch_execute(&stmt).await?;
+    }
+    Ok(())
+}
+
+// ddl takes no bind parameters, so names and passwords are quoted here
+fn ident(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+fn literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_port() {
+        let hp = host_port("postgres://u:p@db.example.com:6543/x").unwrap();
+        assert_eq!(hp, ("db.example.com".to_string(), 6543));
+        let hp = host_port("host=127.0.0.1 user=postgres").unwrap();
+        assert_eq!(hp, ("127.0.0.1".to_string(), 5432));
+        assert_eq!(ident("a\"b"), "\"a\"\"b\"");
+        assert_eq!(literal("it's"), "'it''s'");
+    }
+}
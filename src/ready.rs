@@ -0,0 +1,238 @@
+use crate::config::ReadyConfig;
+use crate::dock::{exec, sleep};
+use crate::grpc::lnd::unlocker::{LndUnlocker, WalletState};
+use crate::images::{BtcNode, ClnNode, LndNode, Node, ProxyNode, BTC_RPC_PORT};
+use crate::postgres;
+use crate::state::State;
+use crate::utils::container_name;
+use anyhow::{anyhow, Result};
+use bollard::Docker;
+use rocket::tokio;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+// retry "check" with exponential backoff until it succeeds or times out
+pub async fn retry<F, Fut, T>(name: &str, conf: &ReadyConfig, check: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let deadline = Instant::now() + Duration::from_secs(conf.timeout_secs);
+    let mut backoff = conf.backoff_millis;
+    loop {
+        match check().await {
+            Ok(t) => return Ok(t),
+            Err(e) => {
+                if Instant::now() >= deadline {
+                    return Err(anyhow!(
+                        "{} not ready after {}s: {}",
+                        name,
+                        conf.timeout_secs,
+                        e
+                    ));
+                }
+                log::debug!("{} not ready yet: {}", name, e);
+            }
+        }
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(conf.max_backoff_millis);
+    }
+}
+
+// wait until the node answers its readiness probe
+pub async fn wait_ready(
+    docker: &Docker,
+    proj: &str,
+    node: &Node,
+    id: &str,
+    conf: &ReadyConfig,
+    state: &State,
+) -> Result<()> {
+    let name = node.name();
+    match node {
+        Node::Btc(b) => retry(&name, conf, || btc_ready(docker, proj, b, state)).await,
+        Node::Lnd(l) => retry(&name, conf, || lnd_ready(proj, l, state)).await,
+        // a private proxy can't be reached from here, trust that it started
+        Node::Proxy(p) if !state.ports.contains_key(&p.name) => Ok(()),
+        Node::Proxy(p) => retry(&name, conf, || proxy_ready(p, state)).await,
+        Node::Cln(c) => retry(&name, conf, || cln_ready(docker, c, id)).await,
+        Node::Relay(r) if !state.ports.contains_key(&r.name) => Ok(()),
+        Node::Relay(r) => retry(&name, conf, || http_ready(&r.name, &r.port, state)).await,
+        Node::Tribes(t) if !state.ports.contains_key(&t.name) => Ok(()),
+        Node::Tribes(t) => retry(&name, conf, || http_ready(&t.name, &t.port, state)).await,
+        Node::Meme(m) if !state.ports.contains_key(&m.name) => Ok(()),
+        Node::Meme(m) => retry(&name, conf, || http_ready(&m.name, &m.port, state)).await,
+        // published by the tribes container
+        Node::Broker(b) if !state.ports.contains_key(&b.tribes) => Ok(()),
+        Node::Broker(b) => retry(&name, conf, || tcp_ready(&b.tribes, &b.port, state)).await,
+        Node::Postgres(p) => {
+            let url = postgres::admin_url(p, state);
+            retry(&name, conf, || postgres::ping(&url)).await
+        }
+    }
+}
+
+pub async fn btc_rpc(btc: &BtcNode, state: &State, method: &str) -> Result<Value> {
+    let body = json!({
+        "jsonrpc": "1.0",
+        "id": "swarm",
+        "method": method,
+        "params": [],
+    });
+    let url = match &btc.external {
+        Some(ep) => format!("http://{}:{}", ep.host, ep.port),
+        None => format!(
+            "http://localhost:{}",
+            state.host_port(&btc.name, BTC_RPC_PORT)
+        ),
+    };
+    let res: Value = reqwest::Client::new()
+        .post(url)
+        .basic_auth(&btc.user, Some(&btc.pass))
+        .json(&body)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await?
+        .json()
+        .await?;
+    if !res["error"].is_null() {
+        return Err(anyhow!("bitcoind {} error: {}", method, res["error"]));
+    }
+    Ok(res["result"].clone())
+}
+
+// bitcoin-cli inside the container, for a bitcoind without published ports
+pub async fn btc_cli(docker: &Docker, proj: &str, btc: &BtcNode, method: &str) -> Result<Value> {
+    let cmd = format!(
+        "bitcoin-cli -rpcport={} -rpcuser={} -rpcpassword={} {}",
+        BTC_RPC_PORT, btc.user, btc.pass, method
+    );
+    let out = exec(docker, &container_name(proj, &btc.name), &cmd).await?;
+    Ok(serde_json::from_str(out.trim())?)
+}
+
+// over rpc if the port is published or bitcoind is external
+pub async fn btc_call(
+    docker: &Docker,
+    proj: &str,
+    btc: &BtcNode,
+    state: &State,
+    method: &str,
+) -> Result<Value> {
+    if btc.external.is_some() || state.ports.contains_key(&btc.name) {
+        btc_rpc(btc, state, method).await
+    } else {
+        btc_cli(docker, proj, btc, method).await
+    }
+}
+
+async fn btc_ready(docker: &Docker, proj: &str, btc: &BtcNode, state: &State) -> Result<()> {
+    btc_call(docker, proj, btc, state, "getblockchaininfo").await?;
+    Ok(())
+}
+
+// REST client for lnd's published http port, with its generated tls cert
+pub async fn lnd_unlocker(proj: &str, lnd: &LndNode, state: &State) -> Result<LndUnlocker> {
+    let http_port = lnd
+        .http_port
+        .as_ref()
+        .ok_or(anyhow!("{} has no http port", lnd.name))?;
+    let cert_path = format!("vol/{}/{}/tls.cert", proj, lnd.name);
+    let host_port = state.host_port(&lnd.name, http_port);
+    LndUnlocker::new(&host_port, &cert_path).await
+}
+
+// the REST state endpoint needs no macaroon
+pub async fn lnd_state(proj: &str, lnd: &LndNode, state: &State) -> Result<WalletState> {
+    Ok(lnd_unlocker(proj, lnd, state).await?.state().await?)
+}
+
+async fn lnd_ready(proj: &str, lnd: &LndNode, state: &State) -> Result<()> {
+    lnd_state(proj, lnd, state).await?;
+    Ok(())
+}
+
+// wait until lnd has been unlocked and its rpc server is up
+pub async fn wait_lnd_active(
+    proj: &str,
+    lnd: &LndNode,
+    conf: &ReadyConfig,
+    state: &State,
+) -> Result<()> {
+    retry(&lnd.name, conf, || async {
+        match lnd_state(proj, lnd, state).await? {
+            st if st.is_active() => Ok(()),
+            st => Err(anyhow!("wallet state {:?}", st)),
+        }
+    })
+    .await
+}
+
+async fn proxy_ready(proxy: &ProxyNode, state: &State) -> Result<()> {
+    tcp_ready(&proxy.name, &proxy.admin_port, state).await
+}
+
+// without blocking a runtime thread that rocket is also serving on
+async fn tcp_ready(node: &str, port: &str, state: &State) -> Result<()> {
+    let addr = format!("127.0.0.1:{}", state.host_port(node, port));
+    let connect = tokio::net::TcpStream::connect(&addr);
+    tokio::time::timeout(Duration::from_secs(2), connect)
+        .await
+        .map_err(|_| anyhow!("connect to {} timed out", addr))??;
+    Ok(())
+}
+
+// any http answer will do, relay, tribes and meme have no health endpoint
+async fn http_ready(node: &str, port: &str, state: &State) -> Result<()> {
+    let port = state.host_port(node, port);
+    reqwest::Client::new()
+        .get(format!("http://127.0.0.1:{}/", port))
+        .timeout(Duration::from_secs(2))
+        .send()
+        .await?;
+    Ok(())
+}
+
+async fn cln_ready(docker: &Docker, cln: &ClnNode, id: &str) -> Result<()> {
+    let cmd = format!("lightning-cli --network={} getinfo", cln.network);
+    let out = exec(docker, id, &cmd).await?;
+    let info: Value = serde_json::from_str(&out)?;
+    if info["id"].is_null() {
+        return Err(anyhow!("getinfo has no id: {}", out));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn quick(timeout_secs: u64) -> ReadyConfig {
+        ReadyConfig {
+            timeout_secs,
+            backoff_millis: 1,
+            max_backoff_millis: 4,
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_retry_until_ready() {
+        let tries = AtomicU32::new(0);
+        let res = retry("node", &quick(5), || async {
+            match tries.fetch_add(1, Ordering::SeqCst) {
+                0..=2 => Err(anyhow!("not yet")),
+                n => Ok(n),
+            }
+        })
+        .await;
+        assert_eq!(res.unwrap(), 3);
+    }
+
+    #[rocket::async_test]
+    async fn test_retry_timeout() {
+        let res: Result<()> = retry("node", &quick(0), || async { Err(anyhow!("down")) }).await;
+        assert!(res.unwrap_err().to_string().contains("node not ready"));
+    }
+}
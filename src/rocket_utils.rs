@@ -23,10 +23,10 @@ impl CmdRequest {
     }
 }
 
-pub struct CORS;
+pub struct Cors;
 
 #[rocket::async_trait]
-impl Fairing for CORS {
+impl Fairing for Cors {
     fn info(&self) -> Info {
         Info {
             name: "Add CORS headers to responses",
@@ -48,6 +48,7 @@ impl Fairing for CORS {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("failed")]
@@ -66,9 +67,7 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
     fn respond_to(self, req: &'r rocket::Request<'_>) -> response::Result<'o> {
         // log `self` to your favored error tracker, e.g.
         // sentry::capture_error(&self);
-        match self {
-            // in our simplistic example, we're happy to respond with the default 500 responder in all cases
-            _ => Status::InternalServerError.respond_to(req),
-        }
+        // we're happy to respond with the default 500 responder in all cases
+        Status::InternalServerError.respond_to(req)
     }
 }
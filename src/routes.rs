@@ -1,5 +1,8 @@
 use crate::logs::{get_log_tx, LogChans, LOGS};
 use crate::rocket_utils::{Error, Result, *};
+use crate::status::project_status;
+use crate::swarm::Swarm;
+use bollard::Docker;
 use response::stream::{Event, EventStream};
 use rocket::serde::json::json;
 use rocket::*;
@@ -8,12 +11,26 @@ use tokio::sync::{broadcast::error::RecvError, mpsc, Mutex};
 
 #[get("/cmd?<tag>&<txt>")]
 pub async fn cmd(sender: &State<mpsc::Sender<CmdRequest>>, tag: &str, txt: &str) -> Result<String> {
-    let (request, reply_rx) = CmdRequest::new(tag, &txt);
-    let _ = sender.send(request).await.map_err(|_| Error::Fail)?;
+    let (request, reply_rx) = CmdRequest::new(tag, txt);
+    sender.send(request).await.map_err(|_| Error::Fail)?;
     let reply = reply_rx.await.map_err(|_| Error::Fail)?;
     Ok(reply)
 }
 
+#[get("/status")]
+pub async fn status(docker: &State<Docker>, swarm: &State<Arc<Swarm>>) -> Result<String> {
+    let st = project_status(docker, &swarm.project, &swarm.nodes, &swarm.state)
+        .await
+        .map_err(|_| Error::Fail)?;
+    Ok(json!(st).to_string())
+}
+
+// where each node's ports are published on the host
+#[get("/ports")]
+pub async fn ports(swarm: &State<Arc<Swarm>>) -> Result<String> {
+    Ok(json!(swarm.state.ports).to_string())
+}
+
 #[get("/logs?<tag>")]
 pub async fn logs(tag: &str) -> Result<String> {
     let lgs = LOGS.lock().await;
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use bollard::container::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::TcpListener;
+
+// {container_port: host_port}
+pub type NodePorts = BTreeMap<String, u16>;
+
+// what a project remembers between runs, in vol/{project}/state.json
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct State {
+    // {node: {container_port: host_port}}
+    #[serde(default)]
+    pub ports: BTreeMap<String, NodePorts>,
+}
+
+impl State {
+    // where a container port of a node is published on the host
+    pub fn host_port(&self, node: &str, port: &str) -> String {
+        match self.ports.get(node).and_then(|ps| ps.get(port)) {
+            Some(hp) => hp.to_string(),
+            None => port.to_string(),
+        }
+    }
+    // give every port a host port, keeping the ones recorded on earlier runs
+    // so existing containers keep their bindings
+    pub fn allocate(&mut self, node: &str, ports: &[String], offset: Option<u16>) -> Result<()> {
+        let mut taken: HashSet<u16> = self
+            .ports
+            .values()
+            .flat_map(|ps| ps.values())
+            .cloned()
+            .collect();
+        let node_ports = self.ports.entry(node.to_string()).or_default();
+        for port in ports {
+            if node_ports.contains_key(port) {
+                continue;
+            }
+            let hp = match offset {
+                Some(o) => {
+                    let p: u16 = port.parse()?;
+                    p.checked_add(o)
+                        .ok_or(anyhow!("port {} + offset {} is out of range", p, o))?
+                }
+                None => free_port(&taken)?,
+            };
+            taken.insert(hp);
+            node_ports.insert(port.to_string(), hp);
+        }
+        Ok(())
+    }
+}
+
+fn free_port(taken: &HashSet<u16>) -> Result<u16> {
+    for _ in 0..100 {
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        if !taken.contains(&port) {
+            return Ok(port);
+        }
+    }
+    Err(anyhow!("could not find a free port"))
+}
+
+pub fn load_state(project: &str) -> Result<State> {
+    let path = format!("vol/{}/state.json", project);
+    match fs::read(path) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(_) => Ok(Default::default()),
+    }
+}
+
+pub fn save_state(project: &str, state: &State) -> Result<()> {
+    fs::create_dir_all(format!("vol/{}", project))?;
+    let path = format!("vol/{}/state.json", project);
+    fs::write(path, serde_json::to_string_pretty(state)?)?;
+    Ok(())
+}
+
+// container ports that the config publishes on the host
+pub fn published_ports(c: &Config<String>) -> Vec<String> {
+    let bindings = c
+        .host_config
+        .as_ref()
+        .and_then(|h| h.port_bindings.as_ref());
+    let mut ports: Vec<String> = bindings
+        .map(|b| {
+            b.keys()
+                .map(|k| k.trim_end_matches("/tcp").to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    ports.sort();
+    ports
+}
+
+// point the published ports of the config at their allocated host ports
+pub fn bind_ports(c: &mut Config<String>, ports: &NodePorts) {
+    let bindings = c
+        .host_config
+        .as_mut()
+        .and_then(|h| h.port_bindings.as_mut());
+    if let Some(bindings) = bindings {
+        for (key, binding) in bindings.iter_mut() {
+            let port = key.trim_end_matches("/tcp");
+            if let (Some(hp), Some(bs)) = (ports.get(port), binding.as_mut()) {
+                for b in bs.iter_mut() {
+                    b.host_port = Some(hp.to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_offset() {
+        let mut st = State::default();
+        let ports = vec!["10009".to_string(), "8881".to_string()];
+        st.allocate("lnd1", &ports, Some(100)).unwrap();
+        assert_eq!(st.host_port("lnd1", "10009"), "10109");
+        assert_eq!(st.host_port("lnd1", "8881"), "8981");
+        // unknown ports fall back to the container port
+        assert_eq!(st.host_port("lnd2", "10009"), "10009");
+    }
+
+    #[test]
+    fn test_allocate_keeps_existing() {
+        let mut st = State::default();
+        let ports = vec!["18443".to_string(), "28332".to_string()];
+        st.allocate("bitcoind", &ports, None).unwrap();
+        let first = st.clone();
+        st.allocate("bitcoind", &ports, None).unwrap();
+        assert_eq!(first.ports, st.ports);
+        let hps = &st.ports["bitcoind"];
+        assert_ne!(hps["18443"], hps["28332"]);
+    }
+}
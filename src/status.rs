@@ -0,0 +1,121 @@
+use crate::dock::{exec, list_containers};
+use crate::grpc::lnd::client::LndClient;
+use crate::images::{ClnNode, Node};
+use crate::ready::btc_call;
+use crate::state::State;
+use crate::utils::{container_name, NODE_LABEL};
+use anyhow::Result;
+use bollard::Docker;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Debug)]
+pub struct NodeStatus {
+    pub name: String,
+    pub role: String,
+    // "running", "exited", "external", or "missing" when there is no container
+    pub state: String,
+    pub image: String,
+    // host_ip:host_port->container_port
+    pub ports: Vec<String>,
+    pub uptime: String,
+    pub health: BTreeMap<String, String>,
+}
+
+pub async fn project_status(
+    docker: &Docker,
+    project: &str,
+    nodes: &[Node],
+    state: &State,
+) -> Result<Vec<NodeStatus>> {
+    let containers = list_containers(docker, project).await?;
+    let mut ret = Vec::new();
+    for node in nodes.iter() {
+        let name = node.name();
+        let container = containers.iter().find(|c| {
+            let labels = c.labels.clone().unwrap_or_default();
+            labels.get(NODE_LABEL) == Some(&name)
+        });
+        let mut st = NodeStatus {
+            name: name.clone(),
+            role: role(node).to_string(),
+            state: "missing".to_string(),
+            image: "".to_string(),
+            ports: vec![],
+            uptime: "".to_string(),
+            health: BTreeMap::new(),
+        };
+        if let Some(c) = container {
+            st.state = c.state.clone().unwrap_or_default();
+            st.image = c.image.clone().unwrap_or_default();
+            st.uptime = c.status.clone().unwrap_or_default();
+            for p in c.ports.clone().unwrap_or_default() {
+                if let Some(public) = p.public_port {
+                    let ip = p.ip.unwrap_or_default();
+                    st.ports
+                        .push(format!("{}:{}->{}", ip, public, p.private_port));
+                }
+            }
+            st.ports.sort();
+            st.ports.dedup();
+        }
+        if node.is_external() {
+            st.state = "external".to_string();
+        }
+        if st.state == "running" || st.state == "external" {
+            st.health = match health(docker, project, node, state).await {
+                Ok(h) => h,
+                Err(e) => BTreeMap::from([("error".to_string(), e.to_string())]),
+            };
+        }
+        ret.push(st);
+    }
+    Ok(ret)
+}
+
+fn role(node: &Node) -> &'static str {
+    match node {
+        Node::Btc(_) => "bitcoind",
+        Node::Lnd(_) => "lnd",
+        Node::Proxy(_) => "proxy",
+        Node::Relay(_) => "relay",
+        Node::Cln(_) => "cln",
+        Node::Postgres(_) => "postgres",
+        Node::Tribes(_) => "tribes",
+        Node::Broker(_) => "broker",
+        Node::Meme(_) => "meme",
+    }
+}
+
+async fn health(
+    docker: &Docker,
+    project: &str,
+    node: &Node,
+    state: &State,
+) -> Result<BTreeMap<String, String>> {
+    let mut h = BTreeMap::new();
+    match node {
+        Node::Btc(b) => {
+            let height = btc_call(docker, project, b, state, "getblockcount").await?;
+            h.insert("height".to_string(), height.to_string());
+        }
+        Node::Lnd(l) => {
+            let info = LndClient::new(project, l, state).await?.get_info().await?;
+            h.insert("synced".to_string(), info.synced_to_chain.to_string());
+            h.insert("pubkey".to_string(), info.identity_pubkey);
+        }
+        Node::Cln(c) => {
+            let info = cln_info(docker, project, c).await?;
+            h.insert("peers".to_string(), info["num_peers"].to_string());
+        }
+        _ => (),
+    }
+    Ok(h)
+}
+
+async fn cln_info(docker: &Docker, project: &str, cln: &ClnNode) -> Result<Value> {
+    let cmd = format!("lightning-cli --network={} getinfo", cln.network);
+    let out = exec(docker, &container_name(project, &cln.name), &cmd).await?;
+    Ok(serde_json::from_str(&out)?)
+}
@@ -0,0 +1,272 @@
+use crate::config::ShutdownConfig;
+use crate::dock::{list_containers, remove_container, remove_network, stop_container};
+use crate::images::{BtcNode, LndNode, Node, PostgresNode, ProxyNode};
+use crate::state::State;
+use crate::utils::NODE_LABEL;
+use anyhow::{anyhow, Result};
+use bollard::Docker;
+use futures_util::FutureExt;
+use rocket::tokio;
+use rocket::tokio::signal::unix::{signal, SignalKind};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+// a started swarm, shared with the api routes
+pub struct Swarm {
+    pub project: String,
+    pub nodes: Vec<Node>,
+    pub state: State,
+}
+
+// sort nodes so that every node comes after its deps
+// fails on duplicate names, missing deps, or cycles
+pub fn order(nodes: Vec<Node>) -> Result<Vec<Node>> {
+    let mut names = HashSet::new();
+    for n in nodes.iter() {
+        if !names.insert(n.name()) {
+            return Err(anyhow!("duplicate node name {}", n.name()));
+        }
+    }
+    for n in nodes.iter() {
+        for d in n.deps() {
+            if !names.contains(&d) {
+                return Err(anyhow!("{} depends on missing node {}", n.name(), d));
+            }
+        }
+    }
+    // Kahn's algorithm, keeping the declared order among ready nodes
+    let mut pending: HashMap<String, usize> = HashMap::new();
+    for n in nodes.iter() {
+        pending.insert(n.name(), n.deps().len());
+    }
+    let mut remaining: Vec<Option<Node>> = nodes.into_iter().map(Some).collect();
+    let mut ordered: Vec<Node> = Vec::new();
+    while ordered.len() < remaining.len() {
+        let next = remaining
+            .iter()
+            .position(|n| matches!(n, Some(n) if pending[&n.name()] == 0));
+        let idx = match next {
+            Some(i) => i,
+            None => {
+                let stuck: Vec<String> = remaining.iter().flatten().map(|n| n.name()).collect();
+                return Err(anyhow!("dependency cycle between {}", stuck.join(", ")));
+            }
+        };
+        let node = remaining[idx].take().unwrap();
+        for n in remaining.iter().flatten() {
+            let satisfied = n.deps().iter().filter(|d| **d == node.name()).count();
+            if let Some(p) = pending.get_mut(&n.name()) {
+                *p -= satisfied;
+            }
+        }
+        ordered.push(node);
+    }
+    Ok(ordered)
+}
+
+// the node and every node that depends on it, directly or not
+pub fn dependents(ordered: &[Node], name: &str) -> HashSet<String> {
+    let mut affected = HashSet::new();
+    affected.insert(name.to_string());
+    for n in ordered.iter() {
+        if n.deps().iter().any(|d| affected.contains(d)) {
+            affected.insert(n.name());
+        }
+    }
+    affected
+}
+
+// run a startup future, turning a panic, Ctrl-C or SIGTERM into an error
+// so the caller can still clean up whatever was already started
+pub async fn guarded<F>(startup: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let mut term = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = AssertUnwindSafe(startup).catch_unwind() => match res {
+            Ok(r) => r,
+            Err(_) => Err(anyhow!("startup panicked")),
+        },
+        _ = tokio::signal::ctrl_c() => Err(anyhow!("interrupted during startup")),
+        _ = term.recv() => Err(anyhow!("terminated during startup")),
+    }
+}
+
+// stop every container of the project in reverse start order, so dependents go first.
+// found by label, so containers recreated since startup (e.g. by "rotate") are included,
+// and ones that aren't in "order" go before all the rest
+pub async fn shutdown(docker: &Docker, project: &str, order: &[String], conf: &ShutdownConfig) {
+    let containers = match list_containers(docker, project).await {
+        Ok(cs) => cs,
+        Err(e) => {
+            log::warn!("failed to list containers: {}", e);
+            return;
+        }
+    };
+    let found: Vec<(String, String)> = containers
+        .into_iter()
+        .filter_map(|c| Some((c.labels?.get(NODE_LABEL)?.clone(), c.id?)))
+        .collect();
+    for (node, id) in stop_order(order, found).iter() {
+        log::info!("=> stopping {}", node);
+        if let Err(e) = stop_container(docker, id, conf.stop_timeout_secs).await {
+            log::warn!("failed to stop {}: {}", node, e);
+        }
+        if conf.keep {
+            continue;
+        }
+        if let Err(e) = remove_container(docker, id).await {
+            log::warn!("failed to remove {}: {}", node, e);
+        }
+    }
+    if !conf.keep {
+        if let Err(e) = remove_network(docker, project).await {
+            log::warn!("failed to remove network: {}", e);
+        }
+    }
+}
+
+// (node, id) pairs, unknown nodes first, then the reverse of "order"
+fn stop_order(order: &[String], mut found: Vec<(String, String)>) -> Vec<(String, String)> {
+    let pos = |node: &str| order.iter().position(|n| n == node);
+    found.sort_by(|a, b| match (pos(&a.0), pos(&b.0)) {
+        (Some(x), Some(y)) => y.cmp(&x),
+        (x, y) => x.cmp(&y),
+    });
+    found
+}
+
+pub fn find_btc<'a>(nodes: &'a [Node], name: &str) -> Result<&'a BtcNode> {
+    nodes
+        .iter()
+        .find_map(|n| match n {
+            Node::Btc(b) if b.name == name => Some(b),
+            _ => None,
+        })
+        .ok_or(anyhow!("no bitcoind named {}", name))
+}
+
+pub fn find_lnd<'a>(nodes: &'a [Node], name: &str) -> Result<&'a LndNode> {
+    nodes
+        .iter()
+        .find_map(|n| match n {
+            Node::Lnd(l) if l.name == name => Some(l),
+            _ => None,
+        })
+        .ok_or(anyhow!("no lnd named {}", name))
+}
+
+pub fn find_proxy<'a>(nodes: &'a [Node], name: &str) -> Result<&'a ProxyNode> {
+    nodes
+        .iter()
+        .find_map(|n| match n {
+            Node::Proxy(p) if p.name == name => Some(p),
+            _ => None,
+        })
+        .ok_or(anyhow!("no proxy named {}", name))
+}
+
+pub fn find_postgres<'a>(nodes: &'a [Node], name: &str) -> Result<&'a PostgresNode> {
+    nodes
+        .iter()
+        .find_map(|n| match n {
+            Node::Postgres(p) if p.name == name => Some(p),
+            _ => None,
+        })
+        .ok_or(anyhow!("no postgres named {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::{ClnNode, RelayNode};
+
+    fn btc(name: &str) -> Node {
+        Node::Btc(BtcNode::new(name, "regtest", "u", "p"))
+    }
+    fn lnd(name: &str, btc: &str) -> Node {
+        Node::Lnd(LndNode::new(name, "regtest", "10009", None, btc))
+    }
+    fn proxy(name: &str, lnd: &str) -> Node {
+        Node::Proxy(ProxyNode::new(
+            name, "regtest", "11111", "5050", "t", "k", lnd,
+        ))
+    }
+
+    #[test]
+    fn test_order() {
+        let nodes = vec![
+            Node::Relay(RelayNode::new("relay1", "3000", "proxy1")),
+            proxy("proxy1", "lnd1"),
+            lnd("lnd1", "bitcoind"),
+            Node::Cln(ClnNode::new("cln1", "regtest", 1, "bitcoind")),
+            btc("bitcoind"),
+        ];
+        let names: Vec<String> = order(nodes).unwrap().iter().map(|n| n.name()).collect();
+        assert_eq!(names, vec!["bitcoind", "lnd1", "proxy1", "relay1", "cln1"]);
+    }
+
+    #[test]
+    fn test_dependents() {
+        let nodes = order(vec![
+            btc("bitcoind"),
+            lnd("lnd1", "bitcoind"),
+            lnd("lnd2", "bitcoind"),
+            proxy("proxy1", "lnd1"),
+        ])
+        .unwrap();
+        let mut deps: Vec<String> = dependents(&nodes, "lnd1").into_iter().collect();
+        deps.sort();
+        assert_eq!(deps, vec!["lnd1", "proxy1"]);
+        assert_eq!(dependents(&nodes, "bitcoind").len(), 4);
+    }
+
+    #[test]
+    fn test_relay_postgres_dep() {
+        let mut relay = RelayNode::new("relay1", "3000", "proxy1");
+        relay.postgres = Some("postgres".to_string());
+        let nodes = order(vec![
+            Node::Relay(relay),
+            btc("bitcoind"),
+            lnd("lnd1", "bitcoind"),
+            proxy("proxy1", "lnd1"),
+            Node::Postgres(PostgresNode::new("postgres", "p")),
+        ])
+        .unwrap();
+        let names: Vec<String> = nodes.iter().map(|n| n.name()).collect();
+        assert_eq!(names.last().unwrap(), "relay1");
+        assert!(dependents(&nodes, "postgres").contains("relay1"));
+    }
+
+    #[test]
+    fn test_stop_order() {
+        let order: Vec<String> = ["bitcoind", "lnd1", "proxy1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let found = ["lnd1", "old", "bitcoind", "proxy1"]
+            .iter()
+            .map(|n| (n.to_string(), format!("id-{}", n)))
+            .collect();
+        let names: Vec<String> = stop_order(&order, found)
+            .into_iter()
+            .map(|(n, _)| n)
+            .collect();
+        assert_eq!(names, vec!["old", "proxy1", "lnd1", "bitcoind"]);
+    }
+
+    #[test]
+    fn test_missing_dep() {
+        let nodes = vec![btc("bitcoind"), proxy("proxy1", "lnd2")];
+        assert!(order(nodes).is_err());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let nodes = vec![lnd("a", "b"), lnd("b", "a")];
+        let err = order(nodes).err().unwrap().to_string();
+        assert!(err.contains("cycle"));
+    }
+}
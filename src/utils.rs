@@ -1,6 +1,8 @@
-use bollard::container::NetworkingConfig;
+use crate::config::Exposure;
+use bollard::container::{Config, NetworkingConfig};
 use bollard::network::CreateNetworkOptions;
-use bollard_stubs::models::{HostConfig, Ipam, IpamConfig, PortBinding, PortMap};
+use bollard_stubs::models::{EndpointSettings, HostConfig, Ipam, IpamConfig, PortBinding, PortMap};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
 pub fn host_config(
@@ -9,22 +11,43 @@ pub fn host_config(
     ports: Vec<&str>,
     vols: Vec<&str>,
     extra_vols: Option<Vec<String>>,
-    links: Option<Vec<&str>>,
 ) -> Option<HostConfig> {
     let mut dvols = default_volumes(project, name, vols);
     if let Some(evs) = extra_vols {
         dvols.extend(evs);
     }
-    let mut c = HostConfig {
+    Some(HostConfig {
         binds: Some(dvols),
         port_bindings: host_port(ports),
         extra_hosts: Some(vec!["host.docker.internal:host-gateway".to_string()]),
+        network_mode: Some(network_name(project)),
         ..Default::default()
-    };
-    if let Some(ls) = links {
-        c.links = Some(ls.iter().map(|l| l.to_string()).collect());
-    }
-    Some(c)
+    })
+}
+
+// hostname of a node, and its alias on the project network
+pub fn domain(name: &str) -> String {
+    format!("{}.sphinx", name)
+}
+
+// docker container names are global, so they carry the project
+pub fn container_name(project: &str, name: &str) -> String {
+    format!("{}-{}", project, name)
+}
+
+pub const PROJECT_LABEL: &str = "sphinx.project";
+pub const ROLE_LABEL: &str = "sphinx.role";
+pub const NODE_LABEL: &str = "sphinx.node";
+// hash of the container config, to detect changes between runs
+pub const HASH_LABEL: &str = "sphinx.hash";
+
+// marks a container as belonging to a project, so "down" only touches our own
+pub fn labels(project: &str, role: &str, name: &str) -> Option<HashMap<String, String>> {
+    let mut ls = HashMap::new();
+    ls.insert(PROJECT_LABEL.to_string(), project.to_string());
+    ls.insert(ROLE_LABEL.to_string(), role.to_string());
+    ls.insert(NODE_LABEL.to_string(), name.to_string());
+    Some(ls)
 }
 
 pub fn exposed_ports(ports: Vec<&str>) -> Option<HashMap<String, HashMap<(), ()>>> {
@@ -63,6 +86,7 @@ pub fn files_volume() -> String {
     format!("{}/files:/files", pwd.to_string_lossy())
 }
 
+// published on localhost only, see "set_exposure" to change that
 fn host_port(ports_in: Vec<&str>) -> Option<PortMap> {
     let mut ports = PortMap::new();
     for port in ports_in {
@@ -70,25 +94,57 @@ fn host_port(ports_in: Vec<&str>) -> Option<PortMap> {
             tcp_port(port),
             Some(vec![PortBinding {
                 host_port: Some(port.to_string()),
-                host_ip: Some("0.0.0.0".to_string()),
-                // host_ip: None,
+                host_ip: Some("127.0.0.1".to_string()),
             }]),
         );
     }
     Some(ports)
 }
 
-pub fn _custom_network() -> CreateNetworkOptions<String> {
+pub fn set_exposure(c: &mut Config<String>, exposure: Exposure) {
+    let hc = match c.host_config.as_mut() {
+        Some(hc) => hc,
+        None => return,
+    };
+    let host_ip = match exposure {
+        Exposure::Private => {
+            hc.port_bindings = None;
+            return;
+        }
+        Exposure::Localhost => "127.0.0.1",
+        Exposure::Public => "0.0.0.0",
+    };
+    for bs in hc
+        .port_bindings
+        .iter_mut()
+        .flat_map(|pm| pm.values_mut())
+        .flatten()
+    {
+        for b in bs.iter_mut() {
+            b.host_ip = Some(host_ip.to_string());
+        }
+    }
+}
+
+// every project gets its own user-defined bridge network
+pub fn network_name(project: &str) -> String {
+    format!("sphinx-{}", project)
+}
+
+pub fn custom_network(project: &str) -> CreateNetworkOptions<String> {
+    let (subnet, gateway) = subnet(project);
+    let mut labels = HashMap::new();
+    labels.insert(PROJECT_LABEL.to_string(), project.to_string());
     CreateNetworkOptions {
-        name: _NET.to_string(),
-        driver: "default".to_string(),
+        name: network_name(project),
+        driver: "bridge".to_string(),
         attachable: true,
+        labels,
         ipam: Ipam {
             driver: Some("default".to_string()),
             config: Some(vec![IpamConfig {
-                subnet: Some("172.18.0.0/16".to_string()),
-                gateway: Some("172.18.0.1".to_string()),
-                ip_range: Some("172.18.5.0/24".to_string()),
+                subnet: Some(subnet),
+                gateway: Some(gateway),
                 ..Default::default()
             }]),
             ..Default::default()
@@ -97,10 +153,37 @@ pub fn _custom_network() -> CreateNetworkOptions<String> {
     }
 }
 
-pub const _NET: &str = "network1";
+// a /24 derived from the project name, so it stays the same across runs
+// (and rust releases, so it has to be a fixed digest rather than DefaultHasher)
+pub fn subnet(project: &str) -> (String, String) {
+    let h = Sha256::digest(project.as_bytes());
+    let a = 200 + h[0] % 50;
+    let b = h[1];
+    (format!("10.{}.{}.0/24", a, b), format!("10.{}.{}.1", a, b))
+}
 
-pub fn _net_config() -> Option<NetworkingConfig<String>> {
+// attach to the project network, reachable by other nodes at "{name}.sphinx"
+pub fn net_config(project: &str, name: &str) -> Option<NetworkingConfig<String>> {
     let mut endpoints_config = HashMap::new();
-    endpoints_config.insert(_NET.to_string(), Default::default());
+    endpoints_config.insert(
+        network_name(project),
+        EndpointSettings {
+            aliases: Some(vec![domain(name)]),
+            ..Default::default()
+        },
+    );
     Some(NetworkingConfig { endpoints_config })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet() {
+        // pinned, an existing project network must keep its subnet
+        let (net, gw) = subnet("stack");
+        assert_eq!(net, "10.210.224.0/24");
+        assert_eq!(gw, "10.210.224.1");
+    }
+}